@@ -15,24 +15,41 @@
 // You should have received a copy of the GNU General Public License
 // along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
 extern crate clap;
+use std::fs;
 use std::process::exit;
-use std::env::current_exe;
+use std::env::{self, current_exe};
+use std::sync::Arc;
 use clap::{App, Arg, AppSettings, ArgGroup, crate_version};
 use crate::wordlist::lines_from_file;
+use crate::rate_limit::parse_rate;
+use crate::state::ScanState;
+use crate::request::{url_host, WORD_PLACEHOLDER};
+use crate::request_template::RequestTemplate;
+use crate::backup;
+use crate::mutation::{self, Rule};
+use crate::filter::{Condition, Filters, HeaderMatch, NumberMatch, parse_regex};
 use atty::Stream;
+use serde::{Serialize, Deserialize};
+
+#[cfg(test)]
+mod tests;
 
 pub struct GlobalOpts {
     pub hostnames: Vec<String>,
     pub wordlist_files: Vec<String>,
+    pub wordlist_dedup: WordlistDedup,
     pub prefixes: Vec<String>,
     pub extensions: Vec<String>,
     pub max_threads: u32,
+    pub concurrency: u32,
     pub proxy_enabled: bool,
     pub proxy_address: String,
     pub proxy_auth_enabled: bool, 
     pub ignore_cert: bool,
     pub show_htaccess: bool,
     pub throttle: u32,
+    pub rate: Option<f64>,
+    pub host_rate: Option<f64>,
     pub max_recursion_depth: Option<i32>,
     pub user_agent: Option<String>,
     pub username: Option<String>,
@@ -40,10 +57,14 @@ pub struct GlobalOpts {
     pub output_file: Option<String>,
     pub json_file: Option<String>,
     pub xml_file: Option<String>,
+    pub jsonl_file: Option<String>,
+    pub save_responses: Option<String>,
     pub verbose: bool,
     pub silent: bool,
     pub timeout: u32,
     pub max_errors: u32,
+    pub retries: u32,
+    pub follow_redirects: u32,
     pub wordlist_split: u32,
     pub scan_listable: bool,
     pub cookies: Option<String>,
@@ -51,27 +72,166 @@ pub struct GlobalOpts {
     pub scrape_listable: bool,
     pub whitelist: bool,
     pub code_list: Vec<u32>,
+    pub filters: Filters,
     pub is_terminal: bool,
     pub no_color:bool,
     pub disable_validator:bool,
-    pub http_verb:HttpVerb,
-    pub scan_opts: ScanOpts
+    pub validator_probes: u32,
+    pub revalidate: u32,
+    // Each path is requested with every verb, the first is also used
+    // to detect and scan directories
+    pub http_verbs: Vec<HttpVerb>,
+    pub tamper: bool,
+    // The body sent with verbs which have one, with the placeholder
+    // replaced by the word being requested
+    pub data: Option<String>,
+    pub content_type: Option<String>,
+    // The url to request for each word, with the placeholder replaced by
+    // the word, if a request file doesn't simply add it to the folder
+    pub url_template: Option<String>,
+    pub vhost: Option<Vhost>,
+    // Found files are requested again with these query parameters added
+    // to find which ones they accept, if a parameter wordlist is given
+    pub parameter_wordlist: Option<String>,
+    pub parameters: Vec<String>,
+    pub parameter_batch: u32,
+    // Found files have their backups requested using these rules, which
+    // are the default rules unless a rule file is given
+    pub backup_rule_file: Option<String>,
+    pub backup_rules: Vec<String>,
+    // Rules making more words from each word in the wordlist, from rule
+    // files and then the command line
+    pub word_rules: Arc<Vec<Rule>>,
+    pub scan_opts: ScanOpts,
+    pub state_file: Option<String>,
+    pub resume_file: Option<String>,
+    // The arguments the scan was started with, saved in the state file
+    pub args: Vec<String>
+}
+
+impl GlobalOpts {
+    // The url to request for each word in a folder, if the word isn't
+    // simply added to the folder
+    pub fn url_template_for(&self, folder: &str) -> Option<String> {
+        if self.vhost.is_some() {
+            Some(format!("{}/", folder.trim_end_matches('/')))
+        }
+        else {
+            self.url_template.clone()
+        }
+    }
+}
+
+// How the Host header is made from each word when scanning for virtual
+// hosts rather than paths
+#[derive(Clone, Debug, PartialEq)]
+pub enum Vhost {
+    // The word as a subdomain of the given domain, or of the host of
+    // the url being requested if there isn't one
+    Subdomain(Option<String>),
+    // The word as it is
+    Raw
+}
+
+impl Vhost {
+    pub fn host(&self, url: &str, word: &str) -> String {
+        match self {
+            Vhost::Subdomain(Some(domain)) => format!("{}.{}", word, domain),
+            Vhost::Subdomain(None) => {
+                let authority = url_host(url);
+                let authority = match authority.find("://") {
+                    Some(index) => &authority[index+3..],
+                    None => &authority
+                };
+                let host = match authority.rfind('@') {
+                    Some(index) => &authority[index+1..],
+                    None => authority
+                };
+                format!("{}.{}", word, host)
+            },
+            Vhost::Raw => String::from(word)
+        }
+    }
+
+    // The Host header sent, with FUZZ in place of the word
+    pub fn pattern(&self) -> String {
+        match self {
+            Vhost::Subdomain(Some(domain)) => format!("{}.{}", WORD_PLACEHOLDER, domain),
+            Vhost::Subdomain(None) => format!("{}.[target host]", WORD_PLACEHOLDER),
+            Vhost::Raw => String::from(WORD_PLACEHOLDER)
+        }
+    }
 }
 
 pub struct ScanOpts {
     pub scan_401: bool,
-    pub scan_403: bool
+    pub scan_403: bool,
+    pub scan_redirects: bool,
+    pub upgrade_https: bool
 }
 
 arg_enum!{
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
     pub enum HttpVerb {
         Get,
         Head,
-        Post
+        Post,
+        Put,
+        Delete,
+        Options,
+        Patch
     }
 }
 
+arg_enum!{
+    // How duplicate words are removed when the wordlists are loaded
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum WordlistDedup {
+        // Sorted and deduplicated in memory
+        Memory,
+        // Sorted and deduplicated in chunks written to temporary files
+        Disk,
+        // Kept in order and deduplicated with a bloom filter, which may
+        // rarely remove a word which isn't a duplicate
+        Bloom,
+        // Kept as they are
+        Off
+    }
+}
+
+impl HttpVerb {
+    // The name of the verb as it is sent in a request
+    pub fn method(&self) -> &'static str {
+        match self {
+            HttpVerb::Get => "GET",
+            HttpVerb::Head => "HEAD",
+            HttpVerb::Post => "POST",
+            HttpVerb::Put => "PUT",
+            HttpVerb::Delete => "DELETE",
+            HttpVerb::Options => "OPTIONS",
+            HttpVerb::Patch => "PATCH"
+        }
+    }
+
+    // Whether requests with the verb send a body
+    pub fn has_body(&self) -> bool {
+        match self {
+            HttpVerb::Post | HttpVerb::Put | HttpVerb::Patch => true,
+            _ => false
+        }
+    }
+}
+
+impl Default for HttpVerb {
+    fn default() -> HttpVerb { HttpVerb::Get }
+}
+
 pub fn get_args() -> GlobalOpts
+{
+    parse_args(env::args().collect())
+}
+
+pub(crate) fn parse_args(raw_args: Vec<String>) -> GlobalOpts
 {
     // Defines all the command line arguments with the Clap module
     let args = App::new("Dirble")
@@ -93,7 +253,10 @@ EXAMPLE USE:
     - Providing a list of extensions and a list of URIs:
         dirble [address] -X wordlists/web.lst -U uri-list.txt\n
     - Providing multiple hosts to scan via command line:
-        dirble [address] -u [address] -u [address]")
+        dirble [address] -u [address] -u [address]\n
+    - Saving the progress of a scan and resuming it after it was stopped:
+        dirble [address] --state-file scan.state
+        dirble --resume scan.state")
         .setting(AppSettings::ArgRequiredElseHelp)
         .arg(Arg::with_name("host")
              .display_order(10)
@@ -131,31 +294,197 @@ headers set will be applied to all URIs")
              .takes_value(true)
              .value_name("uri-file")
              .visible_alias("url-file"))
+        .arg(Arg::with_name("request_file")
+             .conflicts_with("hosts")
+             .display_order(10)
+             .help(
+"The filename of a raw HTTP request, such as one saved from Burp, to
+scan with. FUZZ is replaced with each word wherever it appears in the
+path, headers or body")
+             .long("request-file")
+             .next_line_help(true)
+             .takes_value(true)
+             .value_name("request-file"))
+        .arg(Arg::with_name("vhost")
+             .conflicts_with("request_file")
+             .display_order(10)
+             .help(
+"Scans for virtual hosts rather than paths, requesting the target with
+each word as a subdomain of its host in the Host header")
+             .long("vhost")
+             .next_line_help(true))
+        .arg(Arg::with_name("vhost_domain")
+             .conflicts_with("request_file")
+             .display_order(10)
+             .help(
+"The domain to add each word to when scanning for virtual hosts, for
+targets given by IP address. Implies --vhost")
+             .long("vhost-domain")
+             .next_line_help(true)
+             .takes_value(true)
+             .value_name("domain"))
+        .arg(Arg::with_name("vhost_raw")
+             .conflicts_with("request_file")
+             .conflicts_with("vhost_domain")
+             .display_order(10)
+             .help(
+"Sends each word in the Host header as it is when scanning for virtual
+hosts. Implies --vhost")
+             .long("vhost-raw")
+             .next_line_help(true))
+        .arg(Arg::with_name("request_scheme")
+             .default_value("https")
+             .display_order(10)
+             .help(
+"The scheme to use for a request file which only gives a path")
+             .long("request-scheme")
+             .next_line_help(true)
+             .possible_values(&["http", "https"])
+             .takes_value(true))
         .group(ArgGroup::with_name("hosts")
                .args(&["host", "host_file", "extra_hosts"])
-               .multiple(true)
-               .required(true))
+               .multiple(true))
         .arg(Arg::with_name("http_verb")
              .default_value("Get")
              .display_order(11)
              .help(
-"Specify which HTTP verb to use
+"Specify which HTTP verbs to use, as a comma separated list. Each path is
+requested with every verb, directories are detected using the first
 ") // Newline is needed for the enumeration of possible values
+             .case_insensitive(true)
              .long("verb")
+             .multiple(true)
              .next_line_help(true)
              .possible_values(&HttpVerb::variants())
-             .takes_value(true))
+             .require_delimiter(true)
+             .takes_value(true)
+             .use_delimiter(true))
+        .arg(Arg::with_name("tamper")
+             .display_order(11)
+             .help(
+"Requests paths which return 401, 403 or 405 again with each other verb
+and an X-HTTP-Method-Override header giving the refused verb, and with
+each verb which isn't being scanned, reporting any with a different code")
+             .long("tamper")
+             .next_line_help(true))
+        .arg(Arg::with_name("data")
+             .display_order(12)
+             .help(
+"The body to send with POST, PUT and PATCH requests, FUZZ is replaced
+with the word being requested wherever it appears")
+             .long("data")
+             .next_line_help(true)
+             .short("d")
+             .takes_value(true)
+             .value_name("body"))
+        .arg(Arg::with_name("data_file")
+             .conflicts_with("data")
+             .display_order(12)
+             .help(
+"The name of a file containing the body to send, the same as --data")
+             .long("data-file")
+             .next_line_help(true)
+             .takes_value(true)
+             .value_name("data-file"))
+        .arg(Arg::with_name("content_type")
+             .display_order(12)
+             .help(
+"The Content-Type header to send with a body, defaults to
+application/x-www-form-urlencoded")
+             .long("content-type")
+             .next_line_help(true)
+             .takes_value(true)
+             .value_name("type"))
         .arg(Arg::with_name("wordlist")
              .display_order(20)
              .help(
 "Sets which wordlist to use, defaults to dirble_wordlist.txt in the same
-folder as the executable")
+folder as the executable. Use - to read from stdin, gzipped wordlists
+are decompressed")
              .long("wordlist")
              .multiple(true)
              .next_line_help(true)
              .short("w")
              .takes_value(true)
              .value_name("wordlist"))
+        .arg(Arg::with_name("wordlist_dedup")
+             .case_insensitive(true)
+             .default_value("Memory")
+             .display_order(20)
+             .help(
+"How duplicate words are removed from the wordlists. Memory and disk sort
+the words, disk uses temporary files to hold less in memory. Bloom keeps
+the words in order using much less memory, but may rarely remove a word
+which isn't a duplicate. Off keeps the wordlists as they are")
+             .long("wordlist-dedup")
+             .next_line_help(true)
+             .possible_values(&WordlistDedup::variants())
+             .takes_value(true))
+        .arg(Arg::with_name("parameter_wordlist")
+             .display_order(21)
+             .help(
+"A wordlist of query parameter names, which each file found is requested
+with to find the parameters it accepts")
+             .long("param-wordlist")
+             .next_line_help(true)
+             .takes_value(true)
+             .value_name("param-wordlist"))
+        .arg(Arg::with_name("parameter_batch")
+             .default_value("32")
+             .display_order(21)
+             .help(
+"The number of parameter names to add to each request when looking for
+parameters")
+             .long("param-batch")
+             .next_line_help(true)
+             .takes_value(true)
+             .validator(positive_int_check)
+             .value_name("size"))
+        .arg(Arg::with_name("word_rule_file")
+             .display_order(21)
+             .help(
+"A file of rules making more words from each word in the wordlist, one
+per line. Each rule is a list of operations applied in order: l, u and
+c to lowercase, uppercase or capitalise, t to toggle case, p to
+pluralise, $x and ^x to append or prepend x, $[0-9] to append each
+number in a range, and sXY to replace X with Y")
+             .long("word-rules")
+             .multiple(true)
+             .next_line_help(true)
+             .number_of_values(1)
+             .takes_value(true)
+             .value_name("rule-file"))
+        .arg(Arg::with_name("word_rule")
+             .display_order(21)
+             .help(
+"A rule making more words from each word in the wordlist, in the same
+form as the lines of a rule file, for example \"c $[2020-2024]\"")
+             .long("word-rule")
+             .multiple(true)
+             .next_line_help(true)
+             .number_of_values(1)
+             .takes_value(true)
+             .validator(|rule| Rule::parse(&rule).map(|_| ()))
+             .value_name("rule"))
+        .arg(Arg::with_name("backups")
+             .conflicts_with_all(&["vhost", "vhost_domain", "vhost_raw"])
+             .display_order(22)
+             .help(
+"Request backups and editor files of each file found, such as
+config.php.bak and .config.php.swp")
+             .long("backups")
+             .next_line_help(true))
+        .arg(Arg::with_name("backup_rules")
+             .conflicts_with_all(&["vhost", "vhost_domain", "vhost_raw"])
+             .display_order(22)
+             .help(
+"A file of rules for the backups to request, one per line, with {file},
+{name} and {ext} replaced by the file's name, its name without the
+extension and its extension. Implies --backups")
+             .long("backup-rules")
+             .next_line_help(true)
+             .takes_value(true)
+             .value_name("rule-file"))
         .arg(Arg::with_name("extensions")
              .display_order(30)
              .help(
@@ -222,14 +551,52 @@ per line")
              .next_line_help(true)
              .takes_value(true)
              .visible_alias("oX"))
+        .arg(Arg::with_name("jsonl_file")
+             .display_order(40)
+             .help(
+"Sets a file to write JSON Lines output to, with a line written for each
+result, validator and skipped directory as soon as it is found")
+             .long("jsonl-file")
+             .next_line_help(true)
+             .takes_value(true)
+             .visible_alias("oL"))
         .arg(Arg::with_name("output_all")
              .display_order(41)
              .help(
-"Stores all output types respectively as .txt, .json and .xml")
+"Stores all output types respectively as .txt, .json, .xml and .jsonl")
              .long("output-all")
              .next_line_help(true)
              .takes_value(true)
              .visible_alias("oA"))
+        .arg(Arg::with_name("save_responses")
+             .display_order(41)
+             .help(
+"Saves the headers and body of every response which is reported to a
+folder in the given directory for each host, with an index.txt listing
+the file each url was saved to")
+             .long("save-responses")
+             .next_line_help(true)
+             .takes_value(true)
+             .value_name("directory"))
+        .arg(Arg::with_name("state_file")
+             .display_order(42)
+             .help(
+"Periodically saves the progress of the scan to the given file, so that
+it can be continued with --resume")
+             .long("state-file")
+             .next_line_help(true)
+             .takes_value(true)
+             .value_name("state-file"))
+        .arg(Arg::with_name("resume")
+             .conflicts_with("hosts")
+             .display_order(42)
+             .help(
+"Continues a scan from the given state file, using the options the scan
+was started with")
+             .long("resume")
+             .next_line_help(true)
+             .takes_value(true)
+             .value_name("state-file"))
         .arg(Arg::with_name("proxy")
              .display_order(50)
              .help(
@@ -267,6 +634,17 @@ username and password in the form
              .takes_value(true)
              .validator(positive_int_check)
              .value_name("max-threads"))
+        .arg(Arg::with_name("concurrency")
+             .default_value("1")
+             .display_order(60)
+             .help(
+"The number of requests each thread keeps in flight at once")
+             .long("concurrency")
+             .next_line_help(true)
+             .short("C")
+             .takes_value(true)
+             .validator(positive_int_check)
+             .value_name("requests"))
         .arg(Arg::with_name("wordlist_split")
              .default_value("3")
              .display_order(60)
@@ -286,6 +664,26 @@ username and password in the form
              .takes_value(true)
              .validator(positive_int_check)
              .value_name("milliseconds"))
+        .arg(Arg::with_name("rate")
+             .display_order(61)
+             .help(
+"Maximum rate of requests across all threads, given as requests per
+second, minute or hour, e.g. 50/s")
+             .long("rate")
+             .next_line_help(true)
+             .takes_value(true)
+             .validator(rate_check)
+             .value_name("rate"))
+        .arg(Arg::with_name("host_rate")
+             .display_order(61)
+             .help(
+"Maximum rate of requests to each target host, given in the same form
+as --rate")
+             .long("host-rate")
+             .next_line_help(true)
+             .takes_value(true)
+             .validator(rate_check)
+             .value_name("rate"))
         .arg(Arg::with_name("username")
              .display_order(70)
              .help(
@@ -402,6 +800,140 @@ also disables detection of not found codes")
              .short("B")
              .validator(positive_int_check)
              .value_delimiter(","))
+        .arg(Arg::with_name("filter_size")
+             .display_order(110)
+             .help(
+"Hide responses with a size in a comma separated list of sizes, each given
+as N, a range N-M, >N or <N")
+             .long("filter-size")
+             .min_values(1)
+             .multiple(true)
+             .next_line_help(true)
+             .validator(number_match_check)
+             .value_delimiter(","))
+        .arg(Arg::with_name("match_size")
+             .display_order(110)
+             .help(
+"Only show responses with a size in a comma separated list of sizes, each
+given as N, a range N-M, >N or <N")
+             .long("match-size")
+             .min_values(1)
+             .multiple(true)
+             .next_line_help(true)
+             .validator(number_match_check)
+             .value_delimiter(","))
+        .arg(Arg::with_name("filter_words")
+             .display_order(110)
+             .help(
+"Hide responses with a number of words in a comma separated list, each
+given as N, a range N-M, >N or <N")
+             .long("filter-words")
+             .min_values(1)
+             .multiple(true)
+             .next_line_help(true)
+             .validator(number_match_check)
+             .value_delimiter(","))
+        .arg(Arg::with_name("match_words")
+             .display_order(110)
+             .help(
+"Only show responses with a number of words in a comma separated list,
+each given as N, a range N-M, >N or <N")
+             .long("match-words")
+             .min_values(1)
+             .multiple(true)
+             .next_line_help(true)
+             .validator(number_match_check)
+             .value_delimiter(","))
+        .arg(Arg::with_name("filter_lines")
+             .display_order(110)
+             .help(
+"Hide responses with a number of lines in a comma separated list, each
+given as N, a range N-M, >N or <N")
+             .long("filter-lines")
+             .min_values(1)
+             .multiple(true)
+             .next_line_help(true)
+             .validator(number_match_check)
+             .value_delimiter(","))
+        .arg(Arg::with_name("match_lines")
+             .display_order(110)
+             .help(
+"Only show responses with a number of lines in a comma separated list,
+each given as N, a range N-M, >N or <N")
+             .long("match-lines")
+             .min_values(1)
+             .multiple(true)
+             .next_line_help(true)
+             .validator(number_match_check)
+             .value_delimiter(","))
+        .arg(Arg::with_name("filter_time")
+             .display_order(110)
+             .help(
+"Hide responses which took a time in a comma separated list of times to
+respond, each given as N, a range N-M, >N or <N, in milliseconds unless
+followed by s")
+             .long("filter-time")
+             .min_values(1)
+             .multiple(true)
+             .next_line_help(true)
+             .validator(time_match_check)
+             .value_delimiter(","))
+        .arg(Arg::with_name("match_time")
+             .display_order(110)
+             .help(
+"Only show responses which took a time in a comma separated list of times
+to respond, each given as N, a range N-M, >N or <N, in milliseconds unless
+followed by s")
+             .long("match-time")
+             .min_values(1)
+             .multiple(true)
+             .next_line_help(true)
+             .validator(time_match_check)
+             .value_delimiter(","))
+        .arg(Arg::with_name("filter_regex")
+             .display_order(110)
+             .help(
+"Hide responses with a body matching a regex. Can be given multiple times")
+             .long("filter-regex")
+             .multiple(true)
+             .next_line_help(true)
+             .number_of_values(1)
+             .takes_value(true)
+             .validator(regex_check))
+        .arg(Arg::with_name("match_regex")
+             .display_order(110)
+             .help(
+"Only show responses with a body matching a regex. Can be given multiple
+times")
+             .long("match-regex")
+             .multiple(true)
+             .next_line_help(true)
+             .number_of_values(1)
+             .takes_value(true)
+             .validator(regex_check))
+        .arg(Arg::with_name("filter_header")
+             .display_order(110)
+             .help(
+"Hide responses with a header containing a value, given as \"Name: value\"
+Can be given multiple times")
+             .long("filter-header")
+             .multiple(true)
+             .next_line_help(true)
+             .number_of_values(1)
+             .takes_value(true)
+             .validator(header_match_check))
+        .arg(Arg::with_name("match_header")
+             .display_order(110)
+             .help(
+"Only show responses with a header containing a value, given as
+\"Name: value\". Can be given multiple times to show responses matching any
+of them, and an empty value matches any response with the header")
+             .long("match-header")
+             .multiple(true)
+             .next_line_help(true)
+             .number_of_values(1)
+             .takes_value(true)
+             .validator(header_match_check))
         .arg(Arg::with_name("disable_validator")
              .display_order(110)
              .help(
@@ -409,6 +941,24 @@ also disables detection of not found codes")
              .long("disable-validator")
              .next_line_help(true)
              .takes_value(false))
+        .arg(Arg::with_name("validator_probes")
+             .default_value("3")
+             .display_order(110)
+             .help(
+"The number of random paths requested to detect the not found response of
+a folder, must be at least 2")
+             .long("validator-probes")
+             .next_line_help(true)
+             .validator(probe_count_check))
+        .arg(Arg::with_name("revalidate")
+             .default_value("0")
+             .display_order(110)
+             .help(
+"How often to check that the not found response of a folder hasn't changed
+while it is being scanned, given in seconds. Disabled by default")
+             .long("revalidate")
+             .next_line_help(true)
+             .validator(int_check))
         .arg(Arg::with_name("scan_401")
              .display_order(120)
              .help(
@@ -421,6 +971,19 @@ also disables detection of not found codes")
 "Scan folders if they return 403 - Forbidden frequently")
              .long("scan-403")
              .next_line_help(true))
+        .arg(Arg::with_name("scan_redirects")
+             .conflicts_with("upgrade_https")
+             .display_order(120)
+             .help(
+"Scan HTTP folders even if they redirect every path to HTTPS")
+             .long("scan-redirects")
+             .next_line_help(true))
+        .arg(Arg::with_name("upgrade_https")
+             .display_order(120)
+             .help(
+"Scan the HTTPS version of folders which redirect every path to HTTPS")
+             .long("upgrade-https")
+             .next_line_help(true))
         .arg(Arg::with_name("ignore_cert")
              .help(
 "Ignore the certificate validity for HTTPS")
@@ -441,19 +1004,43 @@ also disables detection of not found codes")
         .arg(Arg::with_name("max_errors")
              .default_value("5")
              .help(
-"The number of consecutive errors a thread can have before it exits,
-set to 0 to disable")
+"The number of consecutive errors a host can have before the threads
+scanning it exit, set to 0 to disable")
              .long("max-errors")
              .next_line_help(true)
              .validator(int_check))
+        .arg(Arg::with_name("retries")
+             .default_value("0")
+             .help(
+"The number of times to retry a request which timed out or had its
+connection reset, waiting twice as long before each retry")
+             .long("retries")
+             .next_line_help(true)
+             .validator(int_check))
+        .arg(Arg::with_name("follow_redirects")
+             .default_value("0")
+             .help(
+"The maximum number of redirects to follow from each response, only
+redirects to the same host are followed. Set to 0 to disable")
+             .long("follow-redirects")
+             .next_line_help(true)
+             .validator(int_check))
         .arg(Arg::with_name("no_color")
              .alias("no-colour")
              .help("Disable coloring of terminal output")
              .long("no-color")
              .next_line_help(true))
-        .get_matches();
+        .get_matches_from(raw_args.clone());
 
-    
+    // Options for a resumed scan are parsed from the saved arguments,
+    // progress carries on being saved to the same file by default
+    if let Some(resume_file) = args.value_of("resume") {
+        let mut global_opts = parse_args(ScanState::load(resume_file).args);
+        global_opts.state_file = Some(String::from(
+            args.value_of("state_file").unwrap_or(resume_file)));
+        global_opts.resume_file = Some(String::from(resume_file));
+        return global_opts;
+    }
 
     let mut hostnames:Vec<String> = Vec::new();
 
@@ -481,6 +1068,14 @@ set to 0 to disable")
         }
     }
 
+    // A request file gives the folder to scan, along with the verb,
+    // headers and body to use
+    let request_template = args.value_of("request_file").map(|filename|
+        RequestTemplate::load(filename, args.value_of("request_scheme").unwrap()));
+    if let Some(template) = &request_template {
+        hostnames.push(template.folder());
+    }
+
     if hostnames.len() == 0 {
         println!("No valid hosts were provided - exiting");
         exit(2);
@@ -548,6 +1143,67 @@ set to 0 to disable")
         }
         headers = Some(temp_headers);
     }
+    if let Some(template) = &request_template {
+        headers.get_or_insert_with(Vec::new).extend(template.headers.iter().cloned());
+    }
+
+    // Each verb is only used once, in the order given
+    // A request file's verb is used unless verbs are given
+    let mut http_verbs: Vec<HttpVerb> = Vec::new();
+    for verb in values_t!(args.values_of("http_verb"), HttpVerb).unwrap() {
+        if !http_verbs.contains(&verb) {
+            http_verbs.push(verb);
+        }
+    }
+    if let Some(template) = &request_template {
+        if args.occurrences_of("http_verb") == 0 {
+            http_verbs = vec![template.verb];
+        }
+    }
+
+    // The body is either given directly or read from a file as it is,
+    // keeping any newlines
+    let data = if args.is_present("data_file") {
+        let filename = args.value_of("data_file").unwrap();
+        Some(fs::read_to_string(filename)
+            .unwrap_or_else(|error| {
+                println!("Reading data file \"{}\" failed: {}", filename, error);
+                exit(2);
+            }))
+    }
+    else if args.is_present("data") {
+        Some(String::from(args.value_of("data").unwrap()))
+    }
+    else {
+        request_template.as_ref().and_then(|template| template.body.clone())
+    };
+
+    // Each parameter name is only tried once
+    let mut parameters = match args.value_of("parameter_wordlist") {
+        Some(filename) => lines_from_file(String::from(filename)),
+        None => Vec::new()
+    };
+    parameters.retain(|name| !name.is_empty());
+    parameters.sort();
+    parameters.dedup();
+
+    let mut word_rules: Vec<Rule> = Vec::new();
+    if let Some(filenames) = args.values_of("word_rule_file") {
+        for filename in filenames {
+            word_rules.extend(mutation::load_rules(filename));
+        }
+    }
+    if let Some(rules) = args.values_of("word_rule") {
+        word_rules.extend(rules.map(|rule| Rule::parse(rule).unwrap()));
+    }
+
+    let mut backup_rules = match args.value_of("backup_rules") {
+        Some(filename) => backup::load_rules(filename),
+        None if args.is_present("backups") => backup::DEFAULT_RULES.iter()
+            .map(|rule| String::from(*rule))
+            .collect(),
+        None => Vec::new()
+    };
 
     let mut whitelist = false;
     let mut code_list:Vec<u32> = Vec::new();
@@ -575,7 +1231,40 @@ set to 0 to disable")
         max_recursion_depth = Some(string_recursion_depth.parse::<i32>().unwrap());
     }
 
-    let mut scan_opts = ScanOpts{scan_401:false, scan_403:false};
+    // Unless a request file puts the word straight after the folder, the
+    // urls requested aren't in the folder so can't be recursed into or
+    // have backups of the files found requested.
+    // Prefixes and extensions are only used if the word is in the url,
+    // so aren't used for virtual hosts either
+    let mut prefixes = load_modifiers(&args, "prefixes");
+    let mut extensions = load_modifiers(&args, "extensions");
+    let mut url_template = None;
+    let vhost = if args.is_present("vhost_raw") {
+        Some(Vhost::Raw)
+    }
+    else if args.is_present("vhost") || args.is_present("vhost_domain") {
+        Some(Vhost::Subdomain(args.value_of("vhost_domain").map(String::from)))
+    } else { None };
+    if vhost.is_some() {
+        max_recursion_depth = Some(0);
+        prefixes = vec![String::new()];
+        extensions = vec![String::new()];
+    }
+    if let Some(template) = &request_template {
+        if !template.is_plain_folder() {
+            max_recursion_depth = Some(0);
+            url_template = Some(template.url.clone());
+            backup_rules.clear();
+        }
+        if !template.url.contains(WORD_PLACEHOLDER) {
+            prefixes = vec![String::new()];
+            extensions = vec![String::new()];
+        }
+    }
+
+    let mut scan_opts = ScanOpts{scan_401:false, scan_403:false,
+        scan_redirects: args.is_present("scan_redirects"),
+        upgrade_https: args.is_present("upgrade_https")};
     if args.is_present("scan_401") || (whitelist && code_list.contains(&401)) {
         scan_opts.scan_401 = true;
     }
@@ -588,10 +1277,14 @@ set to 0 to disable")
     GlobalOpts {
         hostnames,
         wordlist_files: wordlists,
-        prefixes: load_modifiers(&args, "prefixes"),
-        extensions: load_modifiers(&args, "extensions"),
+        wordlist_dedup:
+            value_t!(args.value_of("wordlist_dedup"), WordlistDedup).unwrap(),
+        prefixes,
+        extensions,
         max_threads:
             args.value_of("max_threads").unwrap().parse::<u32>().unwrap(),
+        concurrency:
+            args.value_of("concurrency").unwrap().parse::<u32>().unwrap(),
         proxy_enabled,
         proxy_address,
         proxy_auth_enabled: false,   
@@ -601,6 +1294,14 @@ set to 0 to disable")
             if args.is_present("throttle") {
                 args.value_of("throttle").unwrap().parse::<u32>().unwrap()
             } else { 0 },
+        rate:
+            if args.is_present("rate") {
+                Some(parse_rate(args.value_of("rate").unwrap()).unwrap())
+            } else { None },
+        host_rate:
+            if args.is_present("host_rate") {
+                Some(parse_rate(args.value_of("host_rate").unwrap()).unwrap())
+            } else { None },
         max_recursion_depth,
         user_agent:
             if args.is_present("user_agent") {
@@ -619,11 +1320,19 @@ set to 0 to disable")
         output_file: filename_from_args(&args, "txt"),
         json_file: filename_from_args(&args, "json"),
         xml_file: filename_from_args(&args, "xml"),
+        jsonl_file: filename_from_args(&args, "jsonl"),
+        save_responses:
+            if args.is_present("save_responses") {
+                Some(String::from(args.value_of("save_responses").unwrap()))
+            } else { None },
         verbose: args.is_present("verbose"),
         silent: args.is_present("silent"),
         timeout: args.value_of("timeout").unwrap().parse::<u32>().unwrap(),
         max_errors:
             args.value_of("max_errors").unwrap().parse::<u32>().unwrap(),
+        retries: args.value_of("retries").unwrap().parse::<u32>().unwrap(),
+        follow_redirects:
+            args.value_of("follow_redirects").unwrap().parse::<u32>().unwrap(),
         wordlist_split:
             args.value_of("wordlist_split").unwrap().parse::<u32>().unwrap(),
         scan_listable: args.is_present("scan_listable"),
@@ -632,11 +1341,40 @@ set to 0 to disable")
         scrape_listable: args.is_present("scrape_listable"),
         whitelist,
         code_list,
+        filters: Filters {
+            filters: load_conditions(&args, "filter"),
+            matchers: load_conditions(&args, "match")
+        },
         is_terminal: atty::is(Stream::Stdout),
         no_color: args.is_present("no_color"),
         disable_validator: args.is_present("disable_validator"),
-        http_verb: value_t!(args.value_of("http_verb"), HttpVerb).unwrap(),
-        scan_opts
+        validator_probes:
+            args.value_of("validator_probes").unwrap().parse::<u32>().unwrap(),
+        revalidate:
+            args.value_of("revalidate").unwrap().parse::<u32>().unwrap(),
+        http_verbs,
+        tamper: args.is_present("tamper"),
+        data,
+        content_type:
+            if args.is_present("content_type") {
+                Some(String::from(args.value_of("content_type").unwrap()))
+            } else { None },
+        url_template,
+        vhost,
+        parameter_wordlist: args.value_of("parameter_wordlist").map(String::from),
+        parameters,
+        parameter_batch:
+            args.value_of("parameter_batch").unwrap().parse::<u32>().unwrap(),
+        backup_rule_file: args.value_of("backup_rules").map(String::from),
+        backup_rules,
+        word_rules: Arc::new(word_rules),
+        scan_opts,
+        state_file:
+            if args.is_present("state_file") {
+                Some(String::from(args.value_of("state_file").unwrap()))
+            } else { None },
+        resume_file: None,
+        args: raw_args
     }
 }
 
@@ -666,6 +1404,13 @@ fn filename_from_args(args: &clap::ArgMatches, filetype: &str)
                         String::from(args.value_of("xml_file").unwrap()))
                 }
             }
+            "jsonl" => {
+                extension = "jsonl";
+                if args.is_present("jsonl_file") {
+                    return Some(
+                        String::from(args.value_of("jsonl_file").unwrap()))
+                }
+            }
             _ => panic!()
         }
         if args.is_present("output_all") {
@@ -716,6 +1461,36 @@ fn load_modifiers(args: &clap::ArgMatches, mod_type: &str)
         modifiers
 }
 
+// Parses the values of each of the filter or matcher arguments, they
+// have already been checked by their validators
+fn load_conditions(args: &clap::ArgMatches, mode: &str) -> Vec<Condition> {
+    let mut conditions = Vec::new();
+    let values = |kind: &str| args.values_of(format!("{}_{}", mode, kind))
+        .map(|values| values.collect::<Vec<&str>>())
+        .unwrap_or_default();
+
+    for value in values("size") {
+        conditions.push(Condition::Size(NumberMatch::parse(value).unwrap()));
+    }
+    for value in values("words") {
+        conditions.push(Condition::Words(NumberMatch::parse(value).unwrap()));
+    }
+    for value in values("lines") {
+        conditions.push(Condition::Lines(NumberMatch::parse(value).unwrap()));
+    }
+    for value in values("time") {
+        conditions.push(Condition::Time(NumberMatch::parse_time(value).unwrap()));
+    }
+    for value in values("regex") {
+        conditions.push(Condition::Regex(parse_regex(value).unwrap()));
+    }
+    for value in values("header") {
+        conditions.push(Condition::Header(HeaderMatch::parse(value).unwrap()));
+    }
+
+    conditions
+}
+
 // Validator for the provided host name, ensures that the value begins with http:// or https://
 fn starts_with_http(hostname: String) -> Result<(), String> {
     if hostname.starts_with("https://") || hostname.starts_with("http://") {
@@ -741,6 +1516,40 @@ fn positive_int_check(value: String) -> Result<(), String> {
     return Err(String::from("The number given must be a positive integer."))
 }
 
+// Validator for the --validator-probes flag, a majority can't be found
+// from a single response
+fn probe_count_check(value: String) -> Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(count) if count >= 2 && count <= 20 => Ok(()),
+        _ => Err(String::from("The number of probes must be between 2 and 20."))
+    }
+}
+
+// Validator for the --filter-header and --match-header flags
+fn header_match_check(value: String) -> Result<(), String> {
+    HeaderMatch::parse(&value).map(|_| ())
+}
+
+// Validator for the size, word and line filters and matchers
+fn number_match_check(value: String) -> Result<(), String> {
+    NumberMatch::parse(&value).map(|_| ())
+}
+
+// Validator for the --filter-time and --match-time flags
+fn time_match_check(value: String) -> Result<(), String> {
+    NumberMatch::parse_time(&value).map(|_| ())
+}
+
+// Validator for the --filter-regex and --match-regex flags
+fn regex_check(value: String) -> Result<(), String> {
+    parse_regex(&value).map(|_| ())
+}
+
+// Validator for the --rate and --host-rate flags
+fn rate_check(value: String) -> Result<(), String> {
+    parse_rate(&value).map(|_| ())
+}
+
 // Validator for various arguments, ensures that value is a
 // positive integer, including 0
 fn int_check(value: String) -> Result<(), String> {
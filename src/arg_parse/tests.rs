@@ -0,0 +1,37 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::{parse_args, HttpVerb};
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| String::from(*arg)).collect()
+}
+
+#[test]
+fn verbs_are_split_on_commas() {
+    let global_opts = parse_args(args(&["dirble", "--verb", "POST,PUT", "http://example.com"]));
+    assert_eq!(global_opts.http_verbs, vec![HttpVerb::Post, HttpVerb::Put]);
+    assert_eq!(global_opts.hostnames.len(), 1);
+}
+
+#[test]
+fn verb_is_followed_by_the_host() {
+    // Values after a space aren't taken as more verbs
+    let global_opts = parse_args(args(&["dirble", "--verb", "POST", "http://example.com"]));
+    assert_eq!(global_opts.http_verbs, vec![HttpVerb::Post]);
+    assert_eq!(global_opts.hostnames.len(), 1);
+}
@@ -0,0 +1,120 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::process::exit;
+use crate::wordlist::lines_from_file;
+
+#[cfg(test)]
+mod tests;
+
+// Placeholders in a rule, replaced by the name of the file found, the
+// name without its extension and the extension without its dot
+pub const FILE_PLACEHOLDER: &str = "{file}";
+pub const NAME_PLACEHOLDER: &str = "{name}";
+pub const EXTENSION_PLACEHOLDER: &str = "{ext}";
+
+// Backups and editor artifacts commonly left next to the files they were
+// made from, used when no rule file is given
+pub const DEFAULT_RULES: [&str; 20] = [
+    "{file}.bak",
+    "{file}.old",
+    "{file}.orig",
+    "{file}.save",
+    "{file}.swp",
+    "{file}.tmp",
+    "{file}.txt",
+    "{file}.1",
+    "{file}~",
+    "{file}.zip",
+    "{file}.gz",
+    ".{file}.swp",
+    ".{file}.swo",
+    "#{file}#",
+    "{name}.bak",
+    "{name}.old",
+    "{name}.orig",
+    "{name}.txt",
+    "{name}_backup.{ext}",
+    "{name} - Copy.{ext}",
+];
+
+// Reads rules from a file, one per line, exiting if any of them don't
+// use the name of the file found
+pub fn load_rules(filename: &str) -> Vec<String> {
+    let mut rules = lines_from_file(String::from(filename));
+    rules.retain(|rule| !rule.is_empty());
+
+    for rule in &rules {
+        if let Err(error) = check_rule(rule) {
+            println!("Reading backup rule file \"{}\" failed: {}", filename, error);
+            exit(2);
+        }
+    }
+
+    rules
+}
+
+// A rule without the file or name placeholders would give the same path
+// for every file found
+pub fn check_rule(rule: &str) -> Result<(), String> {
+    if rule.contains(FILE_PLACEHOLDER) || rule.contains(NAME_PLACEHOLDER) {
+        Ok(())
+    }
+    else {
+        Err(format!("the rule \"{}\" doesn't contain {} or {}", rule,
+            FILE_PLACEHOLDER, NAME_PLACEHOLDER))
+    }
+}
+
+// Gives the paths made by applying each rule to the last part of the
+// given path, leaving out any which are the same as the path or as an
+// earlier rule. Rules using the extension are skipped for files without one
+pub fn variants(rules: &[String], path: &str) -> Vec<String> {
+    let (folder, file) = match path.rfind('/') {
+        Some(index) => path.split_at(index + 1),
+        None => ("", path)
+    };
+    if file.is_empty() {
+        return Vec::new();
+    }
+
+    // A leading dot starts a hidden file's name rather than an extension
+    let (name, extension) = match file.rfind('.') {
+        Some(index) if index > 0 => (&file[0..index], Some(&file[index+1..])),
+        _ => (file, None)
+    };
+
+    let mut variants: Vec<String> = Vec::new();
+    for rule in rules {
+        let uses_extension = rule.contains(NAME_PLACEHOLDER) ||
+            rule.contains(EXTENSION_PLACEHOLDER);
+        let variant = match extension {
+            Some(extension) => rule.replace(EXTENSION_PLACEHOLDER, extension),
+            None if uses_extension => continue,
+            None => rule.clone()
+        };
+        let variant = format!("{}{}", folder, variant
+            .replace(FILE_PLACEHOLDER, file)
+            .replace(NAME_PLACEHOLDER, name));
+
+        if variant != path && !variants.contains(&variant) {
+            variants.push(variant);
+        }
+    }
+
+    variants
+}
@@ -0,0 +1,59 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::{check_rule, variants, DEFAULT_RULES};
+
+fn rules(rules: &[&str]) -> Vec<String> {
+    rules.iter().map(|rule| String::from(*rule)).collect()
+}
+
+#[test]
+fn variants_are_made_from_rules() {
+    let rules = rules(&["{file}.bak", "{file}~", ".{file}.swp", "{name}.old",
+        "{name}_backup.{ext}", "{file}.old"]);
+
+    assert_eq!(variants(&rules, "config.php"), vec!["config.php.bak", "config.php~",
+        ".config.php.swp", "config.old", "config_backup.php", "config.php.old"]);
+
+    // Only the file name is changed, not the folders before it
+    assert_eq!(variants(&rules, "admin/db.inc.php")[2], "admin/.db.inc.php.swp");
+    assert_eq!(variants(&rules, "admin/db.inc.php")[3], "admin/db.inc.old");
+
+    // Rules using the extension are skipped for files without one
+    assert_eq!(variants(&rules, "README"), vec!["README.bak", "README~",
+        ".README.swp", "README.old"]);
+    assert_eq!(variants(&rules, ".htpasswd"), vec![".htpasswd.bak", ".htpasswd~",
+        "..htpasswd.swp", ".htpasswd.old"]);
+
+    assert!(variants(&rules, "").is_empty());
+    assert!(variants(&rules, "admin/").is_empty());
+}
+
+#[test]
+fn duplicate_variants_are_removed() {
+    let rules = rules(&["{file}.bak", "{name}.{ext}.bak", "{file}"]);
+    assert_eq!(variants(&rules, "index.html"), vec!["index.html.bak"]);
+}
+
+#[test]
+fn rules_are_checked() {
+    for rule in DEFAULT_RULES.iter() {
+        assert!(check_rule(rule).is_ok(), "{} should be accepted", rule);
+    }
+    assert!(check_rule("{ext}.bak").is_err());
+    assert!(check_rule("backup.zip").is_err());
+}
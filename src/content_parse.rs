@@ -18,6 +18,14 @@
 extern crate select;
 use select::document::Document;
 use select::predicate::Name;
+use percent_encoding::percent_decode;
+
+#[cfg(test)]
+mod tests;
+
+// The number of bits two fingerprints can differ by while still
+// being counted as the same page
+pub const SIMILARITY_THRESHOLD: u32 = 6;
 
 // Returns complete URLs based on the contents of a listable folder
 pub fn scrape_urls(content: String, original_url: String) -> Vec<String>
@@ -73,4 +81,73 @@ pub fn scrape_urls(content: String, original_url: String) -> Vec<String>
     }
 
     output_urls
-}
\ No newline at end of file
+}
+
+// Generates a simhash fingerprint of a page's content, which only
+// changes by a few bits when the content changes slightly. Pages often
+// echo the requested path or include timestamps and tokens which change
+// with every request, so the path is left out and all numbers are
+// treated as the same word
+pub fn simhash(content: &str, url: &str) -> u64 {
+    let last_segment = url.trim_end_matches("/").rsplit("/").next().unwrap_or("");
+    let last_segment = percent_decode(last_segment.as_bytes()).decode_utf8_lossy();
+    let path_words: Vec<String> = words(&last_segment).collect();
+
+    let words: Vec<String> = words(content)
+        .filter(|word| !path_words.contains(word))
+        .map(|word| {
+            if word.chars().any(|c| c.is_ascii_digit()) { String::from("0") }
+            else { word }
+        })
+        .collect();
+
+    // Pairs of words are hashed rather than single words, so that pages
+    // using the same vocabulary in a different order still differ
+    let mut weights = [0i32; 64];
+    let features: Vec<String> = if words.len() < 2 {
+        words
+    }
+    else {
+        words.windows(2).map(|pair| pair.join(" ")).collect()
+    };
+
+    for feature in features {
+        let hash = fnv1a(feature.as_bytes());
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            if hash >> bit & 1 == 1 { *weight += 1 } else { *weight -= 1 }
+        }
+    }
+
+    let mut fingerprint = 0;
+    for (bit, weight) in weights.iter().enumerate() {
+        if *weight > 0 {
+            fingerprint |= 1 << bit;
+        }
+    }
+    fingerprint
+}
+
+// Returns true if the fingerprints are close enough to be the same page
+#[inline]
+pub fn is_similar(fingerprint_a: u64, fingerprint_b: u64) -> bool {
+    (fingerprint_a ^ fingerprint_b).count_ones() <= SIMILARITY_THRESHOLD
+}
+
+// Splits text into lowercase alphanumeric words
+fn words<'a>(text: &'a str) -> impl Iterator<Item = String> + 'a {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+}
+
+// A hash which doesn't change between runs, so fingerprints can be
+// saved in the state file
+#[inline]
+fn fnv1a(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
@@ -0,0 +1,66 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::{simhash, is_similar};
+
+const NOT_FOUND_PAGE: &str = "<html><head><title>Page not found</title></head>
+<body><h1>Sorry, we couldn't find that page</h1>
+<p>The page {PATH} doesn't exist, it may have been moved or deleted.</p>
+<p>Generated at {TIME} for request {TOKEN}</p>
+<a href=\"/\">Return to the home page</a></body></html>";
+
+fn not_found_page(path: &str, time: &str, token: &str) -> String {
+    NOT_FOUND_PAGE
+        .replace("{PATH}", path)
+        .replace("{TIME}", time)
+        .replace("{TOKEN}", token)
+}
+
+#[test]
+fn check_simhash_ignores_echoed_path_and_numbers() {
+    let first = simhash(
+        &not_found_page("/abcdefghij", "2019-05-01 10:00:01", "a81f09c2"),
+        "http://example.com/abcdefghij");
+    let second = simhash(
+        &not_found_page("/KLMNOPQRSTUVWXYZabcd", "2019-05-01 10:00:02", "77e0d3b4"),
+        "http://example.com/KLMNOPQRSTUVWXYZabcd");
+
+    assert!(is_similar(first, second),
+        "Not found pages differing by path and timestamp weren't similar");
+}
+
+#[test]
+fn check_simhash_distinguishes_pages() {
+    let not_found = simhash(
+        &not_found_page("/abcdefghij", "2019-05-01 10:00:01", "a81f09c2"),
+        "http://example.com/abcdefghij");
+    let found = simhash(
+        "<html><head><title>Admin login</title></head>
+        <body><form action=\"/admin/login\" method=\"post\">
+        <input name=\"username\"><input name=\"password\" type=\"password\">
+        <button>Sign in</button></form></body></html>",
+        "http://example.com/admin");
+
+    assert!(!is_similar(not_found, found),
+        "A login page was similar to a not found page");
+}
+
+#[test]
+fn check_simhash_empty_body() {
+    assert_eq!(simhash("", "http://example.com/test"), 0);
+    assert!(is_similar(simhash("", "http://example.com/a"), simhash("", "http://example.com/b")));
+}
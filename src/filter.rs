@@ -0,0 +1,204 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+use regex::bytes::Regex;
+use crate::request::{RequestResponse, ResponseHeaders, KEPT_HEADERS};
+
+#[cfg(test)]
+mod tests;
+
+// The filters and matchers which decide whether a response is output
+// A response is hidden if any filter matches it, and if there are any
+// matchers then it is only shown if one of them matches it
+pub struct Filters {
+    pub filters: Vec<Condition>,
+    pub matchers: Vec<Condition>
+}
+
+impl Filters {
+    pub fn is_shown(&self, response: &RequestResponse, body: &[u8]) -> bool {
+        // Paths scraped from a listable directory aren't requested, so
+        // their size, body, headers and timing aren't known to compare
+        if response.found_from_listable {
+            return true;
+        }
+
+        if self.filters.iter().any(|filter| filter.matches(response, body)) {
+            return false;
+        }
+
+        self.matchers.is_empty() ||
+            self.matchers.iter().any(|matcher| matcher.matches(response, body))
+    }
+}
+
+// A single check against a response
+pub enum Condition {
+    Size(NumberMatch),
+    Words(NumberMatch),
+    Lines(NumberMatch),
+    // Compared against the total time of the request in milliseconds
+    Time(NumberMatch),
+    Regex(Regex),
+    Header(HeaderMatch)
+}
+
+impl Condition {
+    pub fn matches(&self, response: &RequestResponse, body: &[u8]) -> bool {
+        match self {
+            Condition::Size(number) => number.matches(response.content_len),
+            Condition::Words(number) => number.matches(response.words),
+            Condition::Lines(number) => number.matches(response.lines),
+            Condition::Time(number) => number.matches(response.timing.total_ms as usize),
+            Condition::Regex(regex) => regex.is_match(body),
+            Condition::Header(header) => header.matches(&response.headers)
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Condition::Size(number) => write!(f, "size {}", number),
+            Condition::Words(number) => write!(f, "words {}", number),
+            Condition::Lines(number) => write!(f, "lines {}", number),
+            Condition::Time(number) => write!(f, "time {}ms", number),
+            Condition::Regex(regex) => write!(f, "regex {}", regex),
+            Condition::Header(header) => write!(f, "header {}", header)
+        }
+    }
+}
+
+pub fn parse_regex(argument: &str) -> Result<Regex, String> {
+    Regex::new(argument).map_err(|error| format!("Invalid regex: {}", error))
+}
+
+// A number or range of numbers given on the command line as "N",
+// "N-M", ">N" or "<N"
+pub enum NumberMatch {
+    Equal(usize),
+    Range(usize, usize),
+    Greater(usize),
+    Less(usize)
+}
+
+impl NumberMatch {
+    pub fn parse(argument: &str) -> Result<NumberMatch, String> {
+        NumberMatch::parse_with(argument, |value| value.parse::<usize>().ok())
+    }
+
+    // Parses a match on a time, each number can be followed by ms or s
+    // and is converted to milliseconds
+    pub fn parse_time(argument: &str) -> Result<NumberMatch, String> {
+        NumberMatch::parse_with(argument, |value| {
+            if value.ends_with("ms") {
+                value[..value.len()-2].trim().parse::<usize>().ok()
+            }
+            else if value.ends_with("s") {
+                value[..value.len()-1].trim().parse::<f64>().ok()
+                    .filter(|seconds| *seconds >= 0.0)
+                    .map(|seconds| (seconds * 1000.0) as usize)
+            }
+            else {
+                value.parse::<usize>().ok()
+            }
+        })
+    }
+
+    fn parse_with<F>(argument: &str, parse_number: F) -> Result<NumberMatch, String>
+        where F: Fn(&str) -> Option<usize> {
+        let argument = argument.trim();
+        let number = |value: &str| parse_number(value.trim())
+            .ok_or_else(|| format!("\"{}\" must be a number, a range N-M, >N or <N.", argument));
+
+        if argument.starts_with(">") {
+            Ok(NumberMatch::Greater(number(&argument[1..])?))
+        }
+        else if argument.starts_with("<") {
+            Ok(NumberMatch::Less(number(&argument[1..])?))
+        }
+        else if let Some(index) = argument.find("-") {
+            let start = number(&argument[..index])?;
+            let end = number(&argument[index+1..])?;
+            if start > end {
+                return Err(format!("The range \"{}\" is backwards.", argument));
+            }
+            Ok(NumberMatch::Range(start, end))
+        }
+        else {
+            Ok(NumberMatch::Equal(number(argument)?))
+        }
+    }
+
+    pub fn matches(&self, value: usize) -> bool {
+        match *self {
+            NumberMatch::Equal(number) => value == number,
+            NumberMatch::Range(start, end) => value >= start && value <= end,
+            NumberMatch::Greater(number) => value > number,
+            NumberMatch::Less(number) => value < number
+        }
+    }
+}
+
+impl fmt::Display for NumberMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NumberMatch::Equal(number) => write!(f, "{}", number),
+            NumberMatch::Range(start, end) => write!(f, "{}-{}", start, end),
+            NumberMatch::Greater(number) => write!(f, ">{}", number),
+            NumberMatch::Less(number) => write!(f, "<{}", number)
+        }
+    }
+}
+
+// Matches responses whose header with the given name contains the value,
+// given on the command line as "Name: value"
+pub struct HeaderMatch {
+    name: String,
+    value: String
+}
+
+impl HeaderMatch {
+    pub fn parse(argument: &str) -> Result<HeaderMatch, String> {
+        let (name, value) = match argument.find(":") {
+            Some(index) => (argument[0..index].trim(), argument[index+1..].trim()),
+            None => (argument.trim(), "")
+        };
+
+        match KEPT_HEADERS.iter().find(|header| header.eq_ignore_ascii_case(name)) {
+            Some(header) => Ok(HeaderMatch {
+                name: String::from(*header),
+                value: value.to_lowercase()
+            }),
+            None => Err(format!("Only the {} headers can be matched.", KEPT_HEADERS.join(", ")))
+        }
+    }
+
+    // The value is compared case insensitively, and an empty value
+    // matches any response which has the header
+    pub fn matches(&self, headers: &ResponseHeaders) -> bool {
+        headers.get(&self.name).iter()
+            .any(|value| value.to_lowercase().contains(&self.value))
+    }
+}
+
+impl fmt::Display for HeaderMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.value)
+    }
+}
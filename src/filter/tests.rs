@@ -0,0 +1,145 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::{Condition, Filters, HeaderMatch, NumberMatch, parse_regex};
+use crate::request::{self, ResponseHeaders};
+
+#[test]
+fn header_match_parsing() {
+    let header_match = HeaderMatch::parse("content-type: Text/HTML").unwrap();
+    assert_eq!(header_match.to_string(), "Content-Type: text/html");
+
+    let header_match = HeaderMatch::parse("Set-Cookie").unwrap();
+    assert_eq!(header_match.to_string(), "Set-Cookie: ");
+
+    assert!(HeaderMatch::parse("X-Powered-By: PHP").is_err(),
+        "Headers which aren't kept can't be matched");
+}
+
+#[test]
+fn header_matching() {
+    let mut headers = ResponseHeaders::default();
+    headers.content_type = Some("text/html; charset=UTF-8".into());
+    headers.set_cookie = vec!["a=1".into(), "PHPSESSID=abc".into()];
+
+    assert!(HeaderMatch::parse("Content-Type: TEXT/html").unwrap().matches(&headers));
+    assert!(!HeaderMatch::parse("Content-Type: json").unwrap().matches(&headers));
+    assert!(HeaderMatch::parse("Set-Cookie: phpsessid").unwrap().matches(&headers));
+    assert!(HeaderMatch::parse("Set-Cookie:").unwrap().matches(&headers));
+    assert!(!HeaderMatch::parse("Server:").unwrap().matches(&headers),
+        "An empty value shouldn't match a missing header");
+}
+
+#[test]
+fn number_match_parsing() {
+    for (argument, value, expected) in [("10", 10, true), ("10", 11, false),
+            ("5-10", 5, true), ("5-10", 10, true), ("5-10", 11, false),
+            (">10", 11, true), (">10", 10, false),
+            ("<10", 9, true), ("<10", 10, false)].iter() {
+        assert_eq!(NumberMatch::parse(argument).unwrap().matches(*value), *expected,
+            "{} matching {}", argument, value);
+    }
+
+    assert!(NumberMatch::parse("10-5").is_err(), "Backwards ranges should be rejected");
+    assert!(NumberMatch::parse(">ten").is_err());
+    assert_eq!(NumberMatch::parse(" >10 ").unwrap().to_string(), ">10");
+}
+
+#[test]
+fn time_match_parsing() {
+    assert_eq!(NumberMatch::parse_time(">2000ms").unwrap().to_string(), ">2000");
+    assert_eq!(NumberMatch::parse_time(">2s").unwrap().to_string(), ">2000");
+    assert_eq!(NumberMatch::parse_time("0.5s-1500").unwrap().to_string(), "500-1500");
+    assert_eq!(NumberMatch::parse_time("300").unwrap().to_string(), "300");
+    assert!(NumberMatch::parse_time(">2m").is_err());
+}
+
+#[test]
+fn filters_and_matchers() {
+    let mut response = request::fabricate_request_response(
+        String::from("http://example.com/index"), false, false);
+    response.found_from_listable = false;
+    response.code = 200;
+    response.content_len = 1234;
+    response.words = 20;
+    response.lines = 3;
+    response.timing.total_ms = 2500;
+    let body = b"<title>Index of /</title>";
+
+    let no_filters = Filters { filters: Vec::new(), matchers: Vec::new() };
+    assert!(no_filters.is_shown(&response, body));
+
+    let filters = Filters {
+        filters: vec![Condition::Size(NumberMatch::parse("1234").unwrap()),
+                      Condition::Size(NumberMatch::parse("0").unwrap())],
+        matchers: Vec::new()
+    };
+    assert!(!filters.is_shown(&response, body), "Filtered sizes should be hidden");
+
+    let filters = Filters {
+        filters: vec![Condition::Regex(parse_regex("(?i)not found").unwrap())],
+        matchers: vec![Condition::Words(NumberMatch::parse(">10").unwrap())]
+    };
+    assert!(filters.is_shown(&response, body));
+
+    // Any matcher matching is enough for a response to be shown
+    let filters = Filters {
+        filters: Vec::new(),
+        matchers: vec![Condition::Lines(NumberMatch::parse(">100").unwrap()),
+                       Condition::Regex(parse_regex("Index of").unwrap())]
+    };
+    assert!(filters.is_shown(&response, body));
+
+    let filters = Filters {
+        filters: Vec::new(),
+        matchers: vec![Condition::Lines(NumberMatch::parse(">100").unwrap())]
+    };
+    assert!(!filters.is_shown(&response, body), "Unmatched responses should be hidden");
+
+    let filters = Filters {
+        filters: Vec::new(),
+        matchers: vec![Condition::Time(NumberMatch::parse_time(">2000ms").unwrap())]
+    };
+    assert!(filters.is_shown(&response, body), "Slow responses should be matched");
+}
+
+#[test]
+fn scraped_paths_are_not_filtered() {
+    // Paths scraped from a listing are reported with the listing's body,
+    // but weren't requested themselves
+    let response = request::fabricate_request_response(
+        String::from("http://example.com/files/report.pdf"), false, false);
+    let body = b"<title>Index of /files</title>";
+
+    let filters = Filters {
+        filters: vec![Condition::Regex(parse_regex("Index of").unwrap()),
+                      Condition::Size(NumberMatch::parse("0").unwrap())],
+        matchers: Vec::new()
+    };
+    assert!(filters.is_shown(&response, body), "Scraped paths shouldn't be filtered");
+
+    let filters = Filters {
+        filters: Vec::new(),
+        matchers: vec![Condition::Size(NumberMatch::parse(">0").unwrap()),
+                       Condition::Words(NumberMatch::parse(">10").unwrap())]
+    };
+    assert!(filters.is_shown(&response, body), "Scraped paths shouldn't need a matcher");
+
+    let mut requested = response.clone();
+    requested.found_from_listable = false;
+    assert!(!filters.is_shown(&requested, body));
+}
@@ -17,38 +17,91 @@
 
 use std::{
     collections::VecDeque,
-    sync::{Arc, mpsc::{self, Sender, Receiver}},
+    process::exit,
+    sync::{Arc, atomic::{AtomicBool, AtomicUsize, Ordering}, mpsc::{self, Sender, Receiver}},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 #[macro_use]
 extern crate clap;
 extern crate curl;
 mod arg_parse;
+mod rate_limit;
 mod request;
+mod request_engine;
 mod wordlist;
+mod wordlist_store;
 mod output;
 mod content_parse;
 mod output_format;
 mod request_thread;
 mod output_thread;
 mod validator_thread;
+mod state;
+mod response_saver;
+mod filter;
+mod request_template;
+mod param_discovery;
+mod backup;
+mod mutation;
+
+// How often the progress of the scan is written to the state file
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
 
 fn main() {
     // Read the arguments in using the arg_parse module
     let global_opts = Arc::new(arg_parse::get_args());
 
-    output::startup_text(global_opts.clone());
+    let metadata = output::ScanMetadata::new(&global_opts);
 
-    // Get the wordlist file from the arguments and open it
-    let mut wordlist:Vec<String> = Vec::new();
-    for wordlist_file in global_opts.wordlist_files.clone() {
-        wordlist.append(&mut wordlist::lines_from_file(wordlist_file));
-    }
-    wordlist.sort();
-    wordlist.dedup();
-    
-    let wordlist = Arc::new(wordlist);
+    // The first Ctrl-C stops the scan once the requests in flight have
+    // finished so that the report is still written, the second exits
+    let stopping = Arc::new(AtomicBool::new(false));
+    let handler_stopping = stopping.clone();
+    ctrlc::set_handler(move || {
+        if handler_stopping.swap(true, Ordering::SeqCst) {
+            exit(130);
+        }
+        println!("\nStopping the scan once the requests in flight have finished, \
+            press Ctrl-C again to exit immediately");
+    }).expect("Error setting Ctrl-C handler");
+
+    // Get the wordlist files from the arguments and combine them
+    let wordlist = Arc::new(wordlist_store::Wordlist::load(&global_opts.wordlist_files,
+        global_opts.wordlist_dedup));
+
+    output::startup_text(global_opts.clone(), &wordlist);
+
+    // Load the progress of a previous scan if it is being resumed
+    let resume_state = match &global_opts.resume_file {
+        Some(resume_file) => {
+            let resume_state = state::ScanState::load(resume_file);
+            if resume_state.wordlist_len != wordlist.len() {
+                println!("The wordlist has changed since the scan was started, \
+                    resumed scanning may skip or repeat some words");
+            }
+            Some(resume_state)
+        }
+        None => None
+    };
+
+    let resumed_responses = match &resume_state {
+        Some(resume_state) => resume_state.responses.clone(),
+        None => Vec::new()
+    };
+
+    let checkpoint = global_opts.state_file.as_ref().map(|state_file| {
+        let handled_directories = match &resume_state {
+            Some(resume_state) => resume_state.handled_directories.clone(),
+            None => Vec::new()
+        };
+        Arc::new(state::Checkpoint::new(state_file.clone(),
+            global_opts.args.clone(), wordlist.len(),
+            resumed_responses.clone(), handled_directories))
+    });
+
+    let response_saver = global_opts.save_responses.as_ref()
+        .map(|directory| Arc::new(response_saver::ResponseSaver::new(directory)));
 
 
     // Create a channel for threads to communicate with the parent on
@@ -56,13 +109,30 @@ fn main() {
     let (output_tx, output_rx): (Sender<request::RequestResponse>, Receiver<request::RequestResponse>) = mpsc::channel();
     let (to_validate_tx, to_validate_rx): (Sender<request::RequestResponse>, Receiver<request::RequestResponse>) = mpsc::channel();
     let (to_scan_tx, to_scan_rx): (Sender<Option<validator_thread::DirectoryInfo>>, Receiver<Option<validator_thread::DirectoryInfo>>) = mpsc::channel();
+    let (event_tx, event_rx): (Sender<output::ScanEvent>, Receiver<output::ScanEvent>) = mpsc::channel();
+    let (backup_tx, backup_rx): (Sender<wordlist::UriGenerator>, Receiver<wordlist::UriGenerator>) = mpsc::channel();
+
+    // Every request made during the scan shares the one rate limiter
+    let rate_limiter = Arc::new(rate_limit::RateLimiter::new(
+        global_opts.rate, global_opts.host_rate, global_opts.verbose));
 
     let validator_global_opts = global_opts.clone(); 
+    let validator_rate_limiter = rate_limiter.clone();
+    let validator_stopping = stopping.clone();
+    let validator_event_tx = mpsc::Sender::clone(&event_tx);
     let validator_thread = thread::spawn(|| 
-        validator_thread::validator_thread(to_validate_rx, to_scan_tx, validator_global_opts));
+        validator_thread::validator_thread(to_validate_rx, to_scan_tx,
+            validator_event_tx, validator_global_opts, validator_rate_limiter,
+            validator_stopping));
 
 
+    // Hosts which were validated before the scan was stopped are
+    // already in the saved jobs
+    let mut hosts_validating = 0;
     for hostname in &global_opts.hostnames {
+        if is_handled(&checkpoint, hostname) {
+            continue;
+        }
         let mut request = request::fabricate_request_response(hostname.clone(), true, false);
         let mut depth = hostname.matches("/").count() as u32;
         if hostname.ends_with("/") {
@@ -70,46 +140,64 @@ fn main() {
         }
         request.parent_depth = depth;
         to_validate_tx.send(request).unwrap();
+        hosts_validating += 1;
     }
 
 
     // Create a queue for URIs that need to be scanned
     let mut scan_queue: VecDeque<wordlist::UriGenerator> = VecDeque::new();
 
+    if let Some(resume_state) = resume_state {
+        for job in resume_state.jobs {
+            let url_template = global_opts.url_template_for(&job.hostname);
+            scan_queue.push_back(wordlist::UriGenerator::from_state(job,
+                url_template, wordlist.clone(), global_opts.word_rules.clone()));
+        }
+    }
+
     // Push the host URI to the scan queue
-    for _i in 0..global_opts.hostnames.len() {
+    for _i in 0..hosts_validating {
         let response = to_scan_rx.recv().unwrap();
 
-        match response {
-            None => continue,
-            Some(dir_info) => {
-                match &dir_info.validator {
-                    Some(validator) => {
-                        if validator.scan_folder(&global_opts.scan_opts) {
-                            add_dir_to_scan_queue(&mut scan_queue, &global_opts,
-                                                  &dir_info, &wordlist); 
-                        }
-                        else {
-                            println!("Skipping {}{}", dir_info.url, &validator.print_alert())
-                        }
-                    }
-                    // If there is no validator, then scan the folder
-                    None => {
-                        add_dir_to_scan_queue(&mut scan_queue, &global_opts,
-                                              &dir_info, &wordlist); 
-                    }
-                }
-            }
+        if let Some(dir_info) = response {
+            handle_directory(&mut scan_queue, &global_opts, dir_info,
+                             &wordlist, &checkpoint, &event_tx);
         }
 
     }
     // Define the max number of threads and the number of threads currently in use
     let mut threads_in_use = 0;
 
+    // Directories which were found but not yet validated when the scan
+    // was stopped are validated again, followed by an end message so that
+    // the scan doesn't finish before they've been added to the queue
+    let unhandled_directories: Vec<request::RequestResponse> = resumed_responses.iter()
+        .filter(|response| response.is_directory && response.error.is_none()
+            && !is_handled(&checkpoint, &response.url))
+        .cloned()
+        .collect();
+    if !unhandled_directories.is_empty() {
+        for response in unhandled_directories {
+            to_validate_tx.send(response).unwrap();
+        }
+        to_validate_tx.send(request_thread::generate_end()).unwrap();
+        threads_in_use += 1;
+    }
+
     let file_handles = output::create_files(global_opts.clone());
     let output_global_opts = global_opts.clone();
 
-    let output_thread = thread::spawn(|| output_thread::output_thread(output_rx, output_global_opts, file_handles));
+    let output_rate_limiter = rate_limiter.clone();
+    let output_stopping = stopping.clone();
+
+    let output_thread = thread::spawn(|| output_thread::output_thread(output_rx,
+        event_rx, output_global_opts, file_handles, output_rate_limiter, resumed_responses,
+        output_stopping, metadata));
+
+    // The jobs which have been started, along with how far through the
+    // wordlist each has got
+    let mut running_jobs: Vec<(wordlist::JobState, Arc<AtomicUsize>)> = Vec::new();
+    let mut last_save = Instant::now();
 
     // Loop of checking for messages from the threads,
     // spawning new threads on items in the scan queue
@@ -128,51 +216,71 @@ fn main() {
 
                 // Check the validator to see if the directory should be scanned
                 else { 
-                    match &dir_info.validator {
-                        Some(validator) => {
-                            if validator.scan_folder(&global_opts.scan_opts){
-                                add_dir_to_scan_queue(&mut scan_queue, &global_opts,
-                                                      &dir_info, &wordlist);
-                            }
-                            else {
-                                println!("Skipping {}{}", dir_info.url, &validator.print_alert())
-                            }
-                        }
-                        // If there is no validator, then scan the folder
-                        None => {
-                            add_dir_to_scan_queue(&mut scan_queue, &global_opts,
-                                                 &dir_info, &wordlist);
-                        }
-                    }
+                    handle_directory(&mut scan_queue, &global_opts, dir_info,
+                                     &wordlist, &checkpoint, &event_tx);
                 }
             }
         };
 
+        // Jobs requesting the backups of files found are sent before the
+        // thread which found them ends, so are queued before it is counted
+        // as finished
+        while let Ok(uri_gen) = backup_rx.try_recv() {
+            scan_queue.push_back(uri_gen);
+        }
+
+        let is_stopping = stopping.load(Ordering::SeqCst);
+
         // If there are items in the scan queue and available threads
-        // Spawn a new thread to scan an item
-        if threads_in_use < global_opts.max_threads && scan_queue.len() > 0 {
+        // Spawn a new thread to scan an item, unless the scan is stopping
+        if !is_stopping && threads_in_use < global_opts.max_threads
+                && scan_queue.len() > 0 {
 
             // Clone a new sender to the channel and a new wordlist reference
             // Then pop the scan target from the queue
             let to_validate_tx_clone = mpsc::Sender::clone(&to_validate_tx);
             let output_tx_clone = mpsc::Sender::clone(&output_tx);
+            let event_tx_clone = mpsc::Sender::clone(&event_tx);
+            let backup_tx_clone = mpsc::Sender::clone(&backup_tx);
             let list_gen = scan_queue.pop_front().unwrap();
             let arg_clone = global_opts.clone();
+            let rate_limiter_clone = rate_limiter.clone();
+            let checkpoint_clone = checkpoint.clone();
+            let response_saver_clone = response_saver.clone();
+            let stopping_clone = stopping.clone();
+
+            if checkpoint.is_some() {
+                running_jobs.push((list_gen.job_state(), list_gen.progress()));
+            }
 
             // Spawn a thread with the arguments and increment the in use counter
-            thread::spawn(|| request_thread::thread_spawn(to_validate_tx_clone, output_tx_clone, list_gen, arg_clone));
+            thread::spawn(|| request_thread::thread_spawn(to_validate_tx_clone,
+                output_tx_clone, event_tx_clone, backup_tx_clone, list_gen, arg_clone,
+                rate_limiter_clone, checkpoint_clone, response_saver_clone, stopping_clone));
             threads_in_use += 1;
         }
 
         // If there are no threads in use and the queue is empty then stop
-        if threads_in_use == 0 && scan_queue.len() == 0 {
+        // The queue is left for the state file if the scan is stopping
+        if threads_in_use == 0 && (scan_queue.len() == 0 || is_stopping) {
             break;
         }
 
+        if let Some(checkpoint) = &checkpoint {
+            if last_save.elapsed() >= SAVE_INTERVAL {
+                save_state(checkpoint, &scan_queue, &mut running_jobs, wordlist.len());
+                last_save = Instant::now();
+            }
+        }
+
         // Sleep to reduce CPU cycles used by main
         thread::sleep(Duration::from_millis(1));
     }
 
+    if let Some(checkpoint) = &checkpoint {
+        save_state(checkpoint, &scan_queue, &mut running_jobs, wordlist.len());
+    }
+
     // loop to check that report printing has ended
     output_tx.send(generate_end()).unwrap();
     to_validate_tx.send(generate_end()).unwrap();
@@ -180,26 +288,99 @@ fn main() {
     validator_thread.join().unwrap();
 }
 
+// Adds a validated directory to the scan queue, unless its validator
+// shows that it shouldn't be scanned
+fn handle_directory(scan_queue: &mut VecDeque<wordlist::UriGenerator>,
+                    global_opts: &Arc<arg_parse::GlobalOpts>,
+                    dir_info: validator_thread::DirectoryInfo,
+                    wordlist: &Arc<wordlist_store::Wordlist>,
+                    checkpoint: &Option<Arc<state::Checkpoint>>,
+                    event_tx: &Sender<output::ScanEvent>) {
+    // A directory can be found again after a resume, it should
+    // only be scanned once
+    if is_handled(checkpoint, &dir_info.url) {
+        return;
+    }
+
+    match &dir_info.validator {
+        Some(validator) => {
+            if validator.scan_folder(&global_opts.scan_opts) {
+                add_dir_to_scan_queue(scan_queue, global_opts, &dir_info, wordlist);
+            }
+            else {
+                println!("Skipping {}{}", dir_info.url, &validator.print_alert());
+                if let Some(validator_alert) = &validator.validator_alert {
+                    event_tx.send(output::ScanEvent::SkippedDirectory {
+                        url: dir_info.url.clone(),
+                        reason: String::from(validator_alert.reason())
+                    }).unwrap();
+                }
+            }
+        }
+        // If there is no validator, then scan the folder
+        None => {
+            add_dir_to_scan_queue(scan_queue, global_opts, &dir_info, wordlist);
+        }
+    }
+
+    if let Some(checkpoint) = checkpoint {
+        checkpoint.mark_handled(&dir_info.url);
+    }
+}
+
+#[inline]
+fn is_handled(checkpoint: &Option<Arc<state::Checkpoint>>, url: &str) -> bool {
+    match checkpoint {
+        Some(checkpoint) => checkpoint.is_handled(url),
+        None => false
+    }
+}
+
+// Saves the jobs which are still to be scanned, including how far
+// the running jobs have got, to the state file
+fn save_state(checkpoint: &state::Checkpoint,
+              scan_queue: &VecDeque<wordlist::UriGenerator>,
+              running_jobs: &mut Vec<(wordlist::JobState, Arc<AtomicUsize>)>,
+              wordlist_len: usize) {
+    for (job, progress) in running_jobs.iter_mut() {
+        job.current_index = progress.load(Ordering::SeqCst);
+    }
+    running_jobs.retain(|(job, _)| job.current_index <
+        job.words.as_ref().map_or(wordlist_len, Vec::len));
+
+    let mut jobs: Vec<wordlist::JobState> = running_jobs.iter()
+        .map(|(job, _)| job.clone())
+        .collect();
+    jobs.extend(scan_queue.iter().map(|uri_gen| uri_gen.job_state()));
+
+    checkpoint.save(jobs);
+}
+
 #[inline]
 fn add_dir_to_scan_queue(scan_queue: &mut VecDeque<wordlist::UriGenerator>,
                          global_opts: &Arc<arg_parse::GlobalOpts>, 
                          dir_info: &validator_thread::DirectoryInfo,
-                         wordlist: &Arc<Vec<String>>) {
-    for prefix in &global_opts.prefixes {
-        for extension in &global_opts.extensions {
-            for start_index in 0..global_opts.wordlist_split {
-                scan_queue.push_back(
-                    wordlist::UriGenerator::new(
-                        dir_info.url.clone(),
-                        String::from(prefix.clone()),
-                        String::from(extension.clone()),
-                        wordlist.clone(),
-                        start_index,
-                        global_opts.wordlist_split,
-                        dir_info.parent_depth,
-                        dir_info.validator.clone()
-                    )
-                );
+                         wordlist: &Arc<wordlist_store::Wordlist>) {
+    for verb in &global_opts.http_verbs {
+        for prefix in &global_opts.prefixes {
+            for extension in &global_opts.extensions {
+                for start_index in 0..global_opts.wordlist_split {
+                    scan_queue.push_back(
+                        wordlist::UriGenerator::new(
+                            dir_info.url.clone(),
+                            *verb,
+                            String::from(prefix.clone()),
+                            String::from(extension.clone()),
+                            global_opts.url_template_for(&dir_info.url),
+                            wordlist.clone(),
+                            global_opts.word_rules.clone(),
+                            start_index,
+                            global_opts.wordlist_split,
+                            dir_info.parent_depth,
+                            dir_info.validator_for(*verb, prefix, extension)
+                        )
+                    );
+                }
             }
         }
     }
@@ -208,12 +389,24 @@ fn add_dir_to_scan_queue(scan_queue: &mut VecDeque<wordlist::UriGenerator>,
 fn generate_end() -> request::RequestResponse {
     request::RequestResponse {
         url: String::from("MAIN ENDING"),
+        verb: arg_parse::HttpVerb::Get,
+        method_override: None,
+        word: None,
         code: 0,
         content_len: 0,
+        words: 0,
+        lines: 0,
         is_directory:false,
         is_listable: false,
         redirect_url: String::from(""),
+        redirect_chain: None,
         found_from_listable: false,
-        parent_depth: 0
+        derived_from: None,
+        parent_depth: 0,
+        headers: request::ResponseHeaders::default(),
+        timing: request::RequestTiming::default(),
+        parameters: Vec::new(),
+        fingerprint: 0,
+        error: None
     }
 }
\ No newline at end of file
@@ -0,0 +1,264 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{collections::HashSet, process::exit};
+use crate::wordlist::lines_from_file;
+use crate::wordlist_store::Wordlist;
+
+#[cfg(test)]
+mod tests;
+
+// The most numbers a range in an append or prepend can give, each rule's
+// words are all made when the rules are parsed
+const MAX_RANGE_SIZE: u32 = 100_000;
+
+// A single change made to a word by a rule
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operation {
+    // Leaves the word as it is
+    Nothing,
+    Lowercase,
+    Uppercase,
+    // Uppercases the first letter and lowercases the rest
+    Capitalise,
+    // Swaps the case of every letter
+    ToggleCase,
+    // Each of the strings is added to the end or start of the word,
+    // giving a word for each of them
+    Append(Vec<String>),
+    Prepend(Vec<String>),
+    // Replaces every occurrence of the first character with the second
+    Replace(char, char),
+    Pluralise
+}
+
+// A line of a rule file, which is a list of operations separated by
+// spaces applied in order, similar to hashcat rules. For example
+// "c s-_ $[2020-2024]" capitalises the word, replaces dashes with
+// underscores then adds each year to the end
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    pub text: String,
+    operations: Vec<Operation>
+}
+
+impl Rule {
+    pub fn parse(text: &str) -> Result<Rule, String> {
+        let operations = text.split_whitespace()
+            .map(parse_operation)
+            .collect::<Result<Vec<Operation>, String>>()?;
+
+        if operations.is_empty() {
+            return Err(String::from("the rule is empty"));
+        }
+
+        Ok(Rule {
+            text: text.split_whitespace().collect::<Vec<&str>>().join(" "),
+            operations
+        })
+    }
+
+    // The words made by applying the rule to the word, there can be more
+    // than one if the rule adds a range of numbers
+    pub fn apply(&self, word: &str) -> Vec<String> {
+        let mut words = vec![String::from(word)];
+        for operation in &self.operations {
+            words = words.iter()
+                .flat_map(|word| apply_operation(operation, word))
+                .collect();
+        }
+        words
+    }
+
+    // The most words the rule can make from a single word, which is the
+    // same for every word
+    pub fn output_count(&self) -> usize {
+        self.operations.iter()
+            .map(|operation| match operation {
+                Operation::Append(strings) | Operation::Prepend(strings) => strings.len(),
+                _ => 1
+            })
+            .product()
+    }
+}
+
+// Reads the rules in a file, one per line, exiting if any are invalid
+// Empty lines and lines starting with # are skipped
+pub fn load_rules(filename: &str) -> Vec<Rule> {
+    lines_from_file(String::from(filename)).iter()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| Rule::parse(line)
+            .unwrap_or_else(|error| {
+                println!("Reading rule file \"{}\" failed on line {}: {}",
+                    filename, index + 1, error);
+                exit(2);
+            }))
+        .collect()
+}
+
+// The words made from the word by every rule, leaving out the word itself
+// and any made by more than one rule, in the order they were made. Empty
+// lines in the wordlist aren't changed
+pub fn mutations(rules: &[Rule], word: &str) -> Vec<String> {
+    let mut mutations: Vec<String> = Vec::new();
+    if word.is_empty() {
+        return mutations;
+    }
+
+    let mut seen: HashSet<String> = HashSet::new();
+    seen.insert(String::from(word));
+    for rule in rules {
+        for mutation in rule.apply(word) {
+            if seen.insert(mutation.clone()) {
+                mutations.push(mutation);
+            }
+        }
+    }
+    mutations
+}
+
+// The most words which will be requested for a wordlist, including the
+// words made by the rules. Found from the number each rule makes rather
+// than by applying them, so duplicates are counted
+pub fn candidate_count(rules: &[Rule], wordlist: &Wordlist) -> usize {
+    let per_word: usize = rules.iter().map(Rule::output_count).sum();
+    wordlist.len() * (1 + per_word)
+}
+
+fn parse_operation(text: &str) -> Result<Operation, String> {
+    let mut chars = text.chars();
+    let function = chars.next().unwrap();
+    let argument = chars.as_str();
+
+    let operation = match function {
+        ':' => Operation::Nothing,
+        'l' => Operation::Lowercase,
+        'u' => Operation::Uppercase,
+        'c' => Operation::Capitalise,
+        't' => Operation::ToggleCase,
+        'p' => Operation::Pluralise,
+        '$' => Operation::Append(parse_strings(argument)?),
+        '^' => Operation::Prepend(parse_strings(argument)?),
+        's' => {
+            let replaced: Vec<char> = argument.chars().collect();
+            if replaced.len() != 2 {
+                return Err(format!("\"{}\" should give two characters to replace", text));
+            }
+            return Ok(Operation::Replace(replaced[0], replaced[1]));
+        }
+        _ => return Err(format!("\"{}\" isn't an operation", text))
+    };
+
+    // Only adding strings and replacing take an argument
+    match operation {
+        Operation::Append(_) | Operation::Prepend(_) => {},
+        _ if !argument.is_empty() =>
+            return Err(format!("\"{}\" doesn't take an argument", function)),
+        _ => {}
+    }
+
+    Ok(operation)
+}
+
+// Parses the argument of an append or prepend, which is either a string
+// or a range of numbers such as [0-9] or [2015-2024]. Numbers in a range
+// are padded with zeroes to the length of the start, so [00-99] gives
+// 00, 01 and so on
+fn parse_strings(argument: &str) -> Result<Vec<String>, String> {
+    if argument.is_empty() {
+        return Err(String::from("there is nothing to add to the word"));
+    }
+
+    if !argument.starts_with('[') || !argument.ends_with(']') {
+        return Ok(vec![String::from(argument)]);
+    }
+
+    let range = &argument[1..argument.len()-1];
+    let invalid = || format!("\"{}\" isn't a range of numbers", argument);
+    let (start, end) = match range.find('-') {
+        Some(index) => (&range[0..index], &range[index+1..]),
+        None => return Err(invalid())
+    };
+    let width = start.len();
+    let start = start.parse::<u32>().map_err(|_| invalid())?;
+    let end = end.parse::<u32>().map_err(|_| invalid())?;
+    if start > end {
+        return Err(invalid());
+    }
+    if end - start >= MAX_RANGE_SIZE {
+        return Err(format!("\"{}\" is a range of more than {} numbers",
+            argument, MAX_RANGE_SIZE));
+    }
+
+    Ok((start..=end)
+        .map(|number| format!("{:0width$}", number, width = width))
+        .collect())
+}
+
+fn apply_operation(operation: &Operation, word: &str) -> Vec<String> {
+    let word = match operation {
+        Operation::Nothing => String::from(word),
+        Operation::Lowercase => word.to_lowercase(),
+        Operation::Uppercase => word.to_uppercase(),
+        Operation::Capitalise => {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars.as_str().to_lowercase().chars())
+                    .collect(),
+                None => String::new()
+            }
+        }
+        Operation::ToggleCase => word.chars()
+            .map(|c| if c.is_uppercase() { c.to_lowercase().collect::<String>() }
+                else { c.to_uppercase().collect() })
+            .collect(),
+        Operation::Append(strings) => return strings.iter()
+            .map(|string| format!("{}{}", word, string))
+            .collect(),
+        Operation::Prepend(strings) => return strings.iter()
+            .map(|string| format!("{}{}", string, word))
+            .collect(),
+        Operation::Replace(from, to) => word.replace(*from, &to.to_string()),
+        Operation::Pluralise => pluralise(word)
+    };
+
+    vec![word]
+}
+
+// Makes the plural of an English noun following the common rules, in
+// capitals if the word ends in one
+fn pluralise(word: &str) -> String {
+    let lowercase = word.to_lowercase();
+    let (stem, ending) = if lowercase.len() > 1 && lowercase.ends_with('y') &&
+            !lowercase[0..lowercase.len()-1].ends_with(|c| "aeiou".contains(c)) {
+        (&word[0..word.len()-1], "ies")
+    }
+    else if ["s", "x", "z", "ch", "sh"].iter().any(|end| lowercase.ends_with(end)) {
+        (word, "es")
+    }
+    else {
+        (word, "s")
+    };
+
+    if word.chars().last().map_or(false, char::is_uppercase) {
+        format!("{}{}", stem, ending.to_uppercase())
+    }
+    else {
+        format!("{}{}", stem, ending)
+    }
+}
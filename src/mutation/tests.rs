@@ -0,0 +1,103 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::{candidate_count, mutations, Rule};
+use crate::wordlist_store::Wordlist;
+
+fn rules(rules: &[&str]) -> Vec<Rule> {
+    rules.iter().map(|rule| Rule::parse(rule).unwrap()).collect()
+}
+
+#[test]
+fn operations_are_applied() {
+    let cases = [
+        ("l", "AdminPanel", vec!["adminpanel"]),
+        ("u", "admin", vec!["ADMIN"]),
+        ("c", "aDMIN", vec!["Admin"]),
+        ("t", "AdMin", vec!["aDmIN"]),
+        (":", "admin", vec!["admin"]),
+        ("$.old", "admin", vec!["admin.old"]),
+        ("^old_", "admin", vec!["old_admin"]),
+        ("$[1-3]", "admin", vec!["admin1", "admin2", "admin3"]),
+        ("$[08-10]", "v", vec!["v08", "v09", "v10"]),
+        ("s-_", "user-profile-edit", vec!["user_profile_edit"]),
+        ("p", "user", vec!["users"]),
+        ("p", "category", vec!["categories"]),
+        ("p", "key", vec!["keys"]),
+        ("p", "status", vec!["statuses"]),
+        ("p", "match", vec!["matches"]),
+        ("p", "FILE", vec!["FILES"]),
+    ];
+
+    for (rule, word, expected) in cases.iter() {
+        assert_eq!(&Rule::parse(rule).unwrap().apply(word), expected,
+            "{} applied to {}", rule, word);
+    }
+}
+
+#[test]
+fn rules_are_composed() {
+    let rule = Rule::parse("  c   s-_ $[2023-2024] ").unwrap();
+    assert_eq!(rule.text, "c s-_ $[2023-2024]");
+    assert_eq!(rule.apply("annual-REPORT"),
+        vec!["Annual_report2023", "Annual_report2024"]);
+
+    assert_eq!(Rule::parse("^[1-2] $[1-2]").unwrap().apply("x"),
+        vec!["1x1", "1x2", "2x1", "2x2"]);
+}
+
+#[test]
+fn mutations_are_deduplicated() {
+    let rules = rules(&["l", "c", "p", "$s", "s-_"]);
+    assert_eq!(mutations(&rules, "user"), vec!["User", "users"]);
+    assert!(mutations(&rules, "").is_empty());
+
+    // Large ranges are deduplicated without comparing every pair
+    let rules = self::rules(&["$[0000-9999]", "$[000-999]", "$[0-9]"]);
+    let mutations = mutations(&rules, "backup");
+    assert_eq!(mutations.len(), 10000 + 1000 + 10);
+    assert_eq!(mutations[0], "backup0000");
+    assert_eq!(mutations[10000], "backup000");
+}
+
+#[test]
+fn candidates_are_counted_from_rules() {
+    let rules = rules(&["l", "c $[1-3]", "^[1-2] $[00-09]"]);
+    assert_eq!(rules.iter().map(Rule::output_count).collect::<Vec<usize>>(), vec![1, 3, 20]);
+
+    let wordlist = Wordlist::from_words(&[String::from("user"), String::from("admin"),
+        String::new()]);
+    assert_eq!(candidate_count(&rules, &wordlist), 3 * 25);
+    assert_eq!(candidate_count(&[], &wordlist), 3);
+}
+
+#[test]
+fn invalid_rules_are_rejected() {
+    let invalid = ["", "x", "lu", "$", "s-", "s-_.", "$[1-]", "$[9-1]", "$[a-z]", "c l$1"];
+
+    for rule in invalid.iter() {
+        assert!(Rule::parse(rule).is_err(), "{:?} should be rejected", rule);
+    }
+}
+
+#[test]
+fn large_ranges_are_rejected() {
+    assert_eq!(Rule::parse("$[0-4000000000]").unwrap_err(),
+        "\"[0-4000000000]\" is a range of more than 100000 numbers");
+    assert!(Rule::parse("^[100000-200000]").is_err());
+    assert_eq!(rules(&["$[00000-99999]"])[0].output_count(), 100_000);
+}
@@ -18,12 +18,24 @@
 use std::fs::File;
 use std::path::Path;
 use std::sync::Arc;
-use crate::request::RequestResponse;
+use crate::request::{DiscoveredParameter, RedirectChain, RequestResponse, RequestTiming,
+    ResponseHeaders};
 use crate::arg_parse::GlobalOpts;
+use crate::mutation;
 use crate::output_format;
+use crate::rate_limit::{BackoffEvent, LatencySummary};
+use crate::validator_thread::TargetValidator;
+use crate::wordlist_store::Wordlist;
 use std::error::Error;
 use std::io::{LineWriter, Write};
 use clap::crate_version;
+use serde::Serialize;
+use chrono::{SecondsFormat, Utc};
+use xml::writer::{EmitterConfig, EventWriter, XmlEvent};
+
+// The version of the JSON and XML report formats, this should be
+// changed along with the schemas in the schemas folder
+const SCHEMA_VERSION: &str = "1.0";
 
 
 // Struct giving access to each current file handle
@@ -31,7 +43,236 @@ use clap::crate_version;
 pub struct FileHandles {
     pub output_file: Option<LineWriter<File>>,
     pub json_file: Option<LineWriter<File>>,
-    pub xml_file: Option<LineWriter<File>>
+    pub xml_file: Option<LineWriter<File>>,
+    pub jsonl_file: Option<LineWriter<File>>
+}
+
+// Information about the scan included at the top of the JSON and XML
+// reports
+#[derive(Serialize)]
+pub struct ScanMetadata {
+    pub dirble_version: String,
+    pub targets: Vec<String>,
+    pub wordlists: Vec<String>,
+    pub prefixes: Vec<String>,
+    pub extensions: Vec<String>,
+    pub start_time: String,
+    pub end_time: Option<String>,
+    pub options: ScanOptions
+}
+
+// The options which affect which requests are made and which results are
+// reported. Credentials, cookies and headers are left out
+#[derive(Serialize)]
+pub struct ScanOptions {
+    pub wordlist_dedup: String,
+    pub http_verbs: Vec<String>,
+    pub tamper: bool,
+    pub data: Option<String>,
+    pub content_type: Option<String>,
+    pub url_template: Option<String>,
+    pub vhost: Option<String>,
+    pub parameter_wordlist: Option<String>,
+    pub parameter_batch: u32,
+    pub backups: bool,
+    pub backup_rule_file: Option<String>,
+    pub word_rules: Vec<String>,
+    pub max_threads: u32,
+    pub concurrency: u32,
+    pub wordlist_split: u32,
+    pub max_recursion_depth: Option<i32>,
+    pub timeout: u32,
+    pub max_errors: u32,
+    pub retries: u32,
+    pub follow_redirects: u32,
+    pub throttle: u32,
+    pub rate: Option<f64>,
+    pub host_rate: Option<f64>,
+    pub whitelist: bool,
+    pub code_list: Vec<u32>,
+    pub filters: Vec<String>,
+    pub matchers: Vec<String>,
+    pub disable_validator: bool,
+    pub validator_probes: u32,
+    pub revalidate: u32,
+    pub scan_401: bool,
+    pub scan_403: bool,
+    pub scan_redirects: bool,
+    pub upgrade_https: bool,
+    pub scan_listable: bool,
+    pub scrape_listable: bool,
+    pub show_htaccess: bool,
+    pub ignore_cert: bool,
+    pub proxy_enabled: bool,
+    pub user_agent: Option<String>
+}
+
+impl ScanMetadata {
+    // Should be created as the scan starts, the end time is set
+    // when the report is written
+    pub fn new(global_opts: &GlobalOpts) -> ScanMetadata {
+        ScanMetadata {
+            dirble_version: String::from(crate_version!()),
+            targets: global_opts.hostnames.clone(),
+            wordlists: global_opts.wordlist_files.clone(),
+            prefixes: global_opts.prefixes.clone(),
+            extensions: global_opts.extensions.clone(),
+            start_time: current_time(),
+            end_time: None,
+            options: ScanOptions {
+                wordlist_dedup: global_opts.wordlist_dedup.to_string().to_lowercase(),
+                http_verbs: global_opts.http_verbs.iter()
+                    .map(|verb| String::from(verb.method()))
+                    .collect(),
+                tamper: global_opts.tamper,
+                data: global_opts.data.clone(),
+                content_type: global_opts.content_type.clone(),
+                url_template: global_opts.url_template.clone(),
+                vhost: global_opts.vhost.as_ref().map(|vhost| vhost.pattern()),
+                parameter_wordlist: global_opts.parameter_wordlist.clone(),
+                parameter_batch: global_opts.parameter_batch,
+                backups: !global_opts.backup_rules.is_empty(),
+                backup_rule_file: global_opts.backup_rule_file.clone(),
+                word_rules: global_opts.word_rules.iter()
+                    .map(|rule| rule.text.clone())
+                    .collect(),
+                max_threads: global_opts.max_threads,
+                concurrency: global_opts.concurrency,
+                wordlist_split: global_opts.wordlist_split,
+                max_recursion_depth: global_opts.max_recursion_depth,
+                timeout: global_opts.timeout,
+                max_errors: global_opts.max_errors,
+                retries: global_opts.retries,
+                follow_redirects: global_opts.follow_redirects,
+                throttle: global_opts.throttle,
+                rate: global_opts.rate,
+                host_rate: global_opts.host_rate,
+                whitelist: global_opts.whitelist,
+                code_list: global_opts.code_list.clone(),
+                filters: global_opts.filters.filters.iter()
+                    .map(ToString::to_string).collect(),
+                matchers: global_opts.filters.matchers.iter()
+                    .map(ToString::to_string).collect(),
+                disable_validator: global_opts.disable_validator,
+                validator_probes: global_opts.validator_probes,
+                revalidate: global_opts.revalidate,
+                scan_401: global_opts.scan_opts.scan_401,
+                scan_403: global_opts.scan_opts.scan_403,
+                scan_redirects: global_opts.scan_opts.scan_redirects,
+                upgrade_https: global_opts.scan_opts.upgrade_https,
+                scan_listable: global_opts.scan_listable,
+                scrape_listable: global_opts.scrape_listable,
+                show_htaccess: global_opts.show_htaccess,
+                ignore_cert: global_opts.ignore_cert,
+                proxy_enabled: global_opts.proxy_enabled,
+                user_agent: global_opts.user_agent.clone()
+            }
+        }
+    }
+}
+
+#[inline]
+fn current_time() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+// The layout of the JSON report
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    schema_version: &'a str,
+    metadata: &'a ScanMetadata,
+    partial_scan: bool,
+    results: Vec<output_format::JsonResult<'a>>,
+    failed_requests: Vec<output_format::JsonFailed<'a>>,
+    backoff_events: Vec<output_format::JsonBackoff<'a>>,
+    latency: &'a Vec<LatencySummary>
+}
+
+// Events written to the JSON Lines output as soon as they happen,
+// each line is tagged with the type of the event
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScanEvent {
+    Response {
+        url: String,
+        method: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        method_override: Option<&'static str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        word: Option<String>,
+        code: u32,
+        size: usize,
+        words: usize,
+        lines: usize,
+        is_directory: bool,
+        is_listable: bool,
+        found_from_listable: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        derived_from: Option<String>,
+        redirect_url: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        redirect_chain: Option<RedirectChain>,
+        timing: RequestTiming,
+        headers: ResponseHeaders,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        parameters: Vec<DiscoveredParameter>,
+        parent_depth: u32
+    },
+    FailedRequest {
+        url: String,
+        error: String
+    },
+    Validator {
+        url: String,
+        method: &'static str,
+        prefix: String,
+        extension: String,
+        validator: TargetValidator
+    },
+    // The not found response of a directory changed during its scan
+    ValidatorChanged {
+        url: String,
+        method: &'static str,
+        prefix: String,
+        extension: String,
+        previous: TargetValidator,
+        validator: TargetValidator
+    },
+    SkippedDirectory {
+        url: String,
+        reason: String
+    }
+}
+
+impl ScanEvent {
+    pub fn from_response(response: &RequestResponse) -> ScanEvent {
+        match &response.error {
+            Some(error) => ScanEvent::FailedRequest {
+                url: response.url.clone(),
+                error: error.clone()
+            },
+            None => ScanEvent::Response {
+                url: response.url.clone(),
+                method: response.verb.method(),
+                method_override: response.method_override.map(|verb| verb.method()),
+                word: response.word.clone(),
+                code: response.code,
+                size: response.content_len,
+                words: response.words,
+                lines: response.lines,
+                is_directory: response.is_directory,
+                is_listable: response.is_listable,
+                found_from_listable: response.found_from_listable,
+                derived_from: response.derived_from.clone(),
+                redirect_url: response.redirect_url.clone(),
+                redirect_chain: response.redirect_chain.clone(),
+                timing: response.timing.clone(),
+                parameters: response.parameters.clone(),
+                headers: response.headers.clone(),
+                parent_depth: response.parent_depth
+            }
+        }
+    }
 }
 
 pub fn print_response(response: &RequestResponse, global_opts: Arc<GlobalOpts>, 
@@ -50,18 +291,33 @@ pub fn print_response(response: &RequestResponse, global_opts: Arc<GlobalOpts>,
 
     output += &output_format::output_suffix(&response, colour);
 
+    output += &output_format::output_parameters(&response);
+
+    if global_opts.verbose {
+        output += &output_format::output_headers(&response);
+    }
+
     Some(output)
 }
 
 // Called after a scan to print the discovered items in a sorted way - deals with saving to files too
-pub fn print_report(responses: Vec<RequestResponse>, global_opts: Arc<GlobalOpts>, file_handles: FileHandles) {
+pub fn print_report(responses: Vec<RequestResponse>, failed_requests: Vec<RequestResponse>,
+    backoff_events: Vec<BackoffEvent>, latency: Vec<LatencySummary>, partial: bool,
+    mut metadata: ScanMetadata,
+    global_opts: Arc<GlobalOpts>, file_handles: FileHandles) {
+    metadata.end_time = Some(current_time());
+
     let responses = sort_responses(responses);
+    let failed_requests = sort_responses(failed_requests);
 
     if (!global_opts.silent || global_opts.verbose) && global_opts.is_terminal {
         println!("\n");
     }
 
-    let report_string = String::from("Dirble Scan Report: \n");
+    let mut report_string = String::from("Dirble Scan Report: \n");
+    if partial {
+        report_string += "The scan was stopped before it finished, these results are partial\n";
+    }
 
     // If stdout is a terminal then write a report to it
     if global_opts.is_terminal
@@ -73,6 +329,15 @@ pub fn print_report(responses: Vec<RequestResponse>, global_opts: Arc<GlobalOpts
                 println!("{}", line);
             }
         }
+        for line in failed_report(&failed_requests) {
+            println!("{}", line);
+        }
+        for line in backoff_report(&backoff_events) {
+            println!("{}", line);
+        }
+        for line in latency_report(&latency) {
+            println!("{}", line);
+        }
     }
     
     
@@ -87,28 +352,128 @@ pub fn print_report(responses: Vec<RequestResponse>, global_opts: Arc<GlobalOpts
                 write_file(&mut handle, file_line);
             }
         }
+        for line in failed_report(&failed_requests) {
+            write_file(&mut handle, format!("{}\n", line));
+        }
+        for line in backoff_report(&backoff_events) {
+            write_file(&mut handle, format!("{}\n", line));
+        }
+        for line in latency_report(&latency) {
+            write_file(&mut handle, format!("{}\n", line));
+        }
     }
 
-    if responses.len() > 0 {
-        if let Some(mut handle) = file_handles.json_file {
-            write_file(&mut handle, String::from("["));
-            for response in &responses[0..responses.len()-1] {
-                let line = format!("{},\n", output_format::output_json(response));
-                write_file(&mut handle, line);
-            }
-            let final_line = format!("{}]", output_format::output_json(&responses[responses.len()-1]));
-            write_file(&mut handle, final_line);
-        }
+    if let Some(mut handle) = file_handles.json_file {
+        let report = JsonReport {
+            schema_version: SCHEMA_VERSION,
+            metadata: &metadata,
+            partial_scan: partial,
+            results: responses.iter().map(output_format::output_json).collect(),
+            failed_requests: failed_requests.iter()
+                .map(output_format::output_json_failed).collect(),
+            backoff_events: backoff_events.iter()
+                .map(output_format::output_json_backoff).collect(),
+            latency: &latency
+        };
+        serde_json::to_writer_pretty(&mut handle, &report).unwrap();
+        write_file(&mut handle, String::from("\n"));
     }
 
     if let Some(mut handle) = file_handles.xml_file {
-        write_file(&mut handle, String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
-        write_file(&mut handle, String::from("<dirble_scan>\n"));
-        for response in &responses {
-            write_file(&mut handle, output_format::output_xml(response));
-        }
-        write_file(&mut handle, String::from("</dirble_scan>"));
+        write_xml_report(&mut handle, &responses, &failed_requests,
+            &backoff_events, &latency, partial, &metadata).unwrap();
+    }
+}
+
+fn write_xml_report(handle: &mut LineWriter<File>, responses: &Vec<RequestResponse>,
+    failed_requests: &Vec<RequestResponse>, backoff_events: &Vec<BackoffEvent>,
+    latency: &Vec<LatencySummary>, partial: bool, metadata: &ScanMetadata)
+    -> xml::writer::Result<()> {
+    let mut writer = EventWriter::new_with_config(handle,
+        EmitterConfig::new().perform_indent(true));
+
+    writer.write(XmlEvent::StartDocument {
+        version: xml::common::XmlVersion::Version10,
+        encoding: Some("UTF-8"),
+        standalone: None
+    })?;
+
+    let mut root = XmlEvent::start_element("dirble_scan")
+        .attr("schema_version", SCHEMA_VERSION);
+    if partial {
+        root = root.attr("partial_scan", "true");
+    }
+    writer.write(root)?;
+
+    output_format::output_xml_metadata(&mut writer, metadata)?;
+    for response in responses {
+        output_format::output_xml(&mut writer, response)?;
+    }
+    for response in failed_requests {
+        output_format::output_xml_failed(&mut writer, response)?;
+    }
+    for event in backoff_events {
+        output_format::output_xml_backoff(&mut writer, event)?;
+    }
+    for summary in latency {
+        output_format::output_xml_latency(&mut writer, summary)?;
+    }
+
+    writer.write(XmlEvent::end_element())?;
+    writer.into_inner().write_all(b"\n")?;
+    Ok(())
+}
+
+// Generates the lines of the report listing requests which failed,
+// empty if there weren't any
+fn failed_report(failed_requests: &Vec<RequestResponse>) -> Vec<String> {
+    let mut lines = Vec::new();
+    if failed_requests.is_empty() { return lines }
+
+    lines.push(String::from("\nFailed requests:"));
+    for response in failed_requests {
+        lines.push(format!("  {}{}", output_format::output_url(response),
+            output_format::output_error(response)));
+    }
+
+    lines
+}
+
+// Generates the lines of the report describing when hosts were backed
+// off from, empty if that never happened
+fn backoff_report(backoff_events: &Vec<BackoffEvent>) -> Vec<String> {
+    let mut lines = Vec::new();
+    if backoff_events.is_empty() { return lines }
+
+    lines.push(String::from("\nBack-off events:"));
+    for event in backoff_events {
+        lines.push(format!("  {} paused for {}s after {}",
+            event.host, event.pause.as_secs(), event.reason));
+    }
+
+    lines
+}
+
+// Generates the lines of the report summarising how long each host took
+// to respond, empty if no requests were made
+fn latency_report(latency: &Vec<LatencySummary>) -> Vec<String> {
+    let mut lines = Vec::new();
+    if latency.is_empty() { return lines }
+
+    lines.push(String::from("\nResponse times:"));
+    for summary in latency {
+        lines.push(format!("  {} {} requests, average {}ms, median {}ms, \
+            95th percentile {}ms, max {}ms", summary.host, summary.requests,
+            summary.average_ms, summary.median_ms, summary.p95_ms, summary.max_ms));
     }
+
+    lines
+}
+
+// Writes an event as a single line of JSON, the LineWriter flushes it
+// to the file straight away
+pub fn write_jsonl(file_writer: &mut LineWriter<File>, event: &ScanEvent) {
+    write_file(file_writer, serde_json::to_string(event).unwrap() + "\n");
 }
 
 // Write a string to the provided LineWriter
@@ -163,10 +528,16 @@ pub fn create_files(global_opts: Arc<GlobalOpts>) -> FileHandles {
         xml_file = generate_handle(filename);
     }
 
+    let mut jsonl_file = None;
+    if let Some(filename) = &global_opts.jsonl_file {
+        jsonl_file = generate_handle(filename);
+    }
+
     FileHandles {
         output_file: output_file,
         json_file: json_file,
-        xml_file: xml_file
+        xml_file: xml_file,
+        jsonl_file: jsonl_file
     }
 }
 
@@ -184,7 +555,7 @@ fn generate_handle(filename: &String) -> Option<LineWriter<File>>
 }
 
 // Prints out start up information
-pub fn startup_text(global_opts: Arc<GlobalOpts>) {
+pub fn startup_text(global_opts: Arc<GlobalOpts>, wordlist: &Wordlist) {
     if !global_opts.is_terminal { return }
 
     println!("Dirble {}", crate_version!());
@@ -193,6 +564,15 @@ pub fn startup_text(global_opts: Arc<GlobalOpts>) {
     println!("Targets: {}", global_opts.hostnames.clone().join(" "));
     println!("Wordlists: {}", global_opts.wordlist_files.clone().join(" "));
 
+    if global_opts.word_rules.is_empty() {
+        println!("Words: {}", wordlist.len());
+    }
+    else {
+        println!("Words: {} (up to {} with {} word rules applied)", wordlist.len(),
+            mutation::candidate_count(&global_opts.word_rules, wordlist),
+            global_opts.word_rules.len());
+    }
+
     if global_opts.prefixes.len() == 1 && global_opts.prefixes[0] == "" {
         println!("No Prefixes");
     }
@@ -15,8 +15,16 @@
 // You should have received a copy of the GNU General Public License
 // along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::request::RequestResponse;
+use std::io::Write;
+use crate::arg_parse::HttpVerb;
+use crate::request::{self, DiscoveredParameter, RedirectChain, RequestResponse, RequestTiming,
+    ResponseHeaders};
+use crate::rate_limit::{BackoffEvent, LatencySummary};
+use crate::output::ScanMetadata;
 use colored::*;
+use serde::Serialize;
+use serde_json::Value;
+use xml::writer::{EventWriter, XmlEvent};
 
 #[cfg(test)]
 mod tests;
@@ -62,9 +70,15 @@ pub fn output_letter(response: &RequestResponse) -> String {
     else { String::from("+ ") }
 }
 
+// The verb is only shown when it isn't a plain GET
 #[inline]
 pub fn output_url(response: &RequestResponse) -> String {
-    format!("{} ", response.url)
+    if response.verb == HttpVerb::Get && response.method_override.is_none() {
+        format!("{} ", response.url)
+    }
+    else {
+        format!("{} {} ", response.verb.method(), response.url)
+    }
 }
 
 #[inline]
@@ -82,53 +96,278 @@ pub fn output_suffix(response: &RequestResponse, color: bool) -> String {
         }
     }
 
-    match response.code {
-        301 | 302 => {
-            format!("(CODE:{}|SIZE:{:#?}|DEST:{})", 
-                code_string, response.content_len, response.redirect_url)
+    let mut suffix = format!("(CODE:{}|SIZE:{:#?}", code_string, response.content_len);
+
+    // Followed redirects show each url in the chain and where it ended
+    match &response.redirect_chain {
+        Some(chain) => {
+            suffix += &format!("|DEST:{}|FINAL_CODE:{}|FINAL_SIZE:{}",
+                chain.urls.join(" -> "), chain.final_code, chain.final_size);
+        }
+        None if request::is_redirect(response.code) => {
+            suffix += &format!("|DEST:{}", response.redirect_url);
+        }
+        None => {}
+    }
+
+    if let Some(method_override) = response.method_override {
+        suffix += &format!("|METHOD_OVERRIDE:{}", method_override.method());
+    }
+
+    if let Some(word) = &response.word {
+        suffix += &format!("|WORD:{}", word);
+    }
+
+    if let Some(derived_from) = &response.derived_from {
+        suffix += &format!("|DERIVED_FROM:{}", derived_from);
+    }
+
+    suffix + ")"
+}
+
+// The parameters found for the response, each on its own line
+#[inline]
+pub fn output_parameters(response: &RequestResponse) -> String {
+    response.parameters.iter()
+        .map(|parameter| format!("\n    PARAM:{} (CODE:{}|SIZE:{}|CHANGED:{})",
+            parameter.name, parameter.code, parameter.size, parameter.change.name()))
+        .collect()
+}
+
+// The kept headers of the response, each on its own line
+#[inline]
+pub fn output_headers(response: &RequestResponse) -> String {
+    response.headers.pairs().iter()
+        .map(|(name, value)| format!("\n    {}: {}", name, value))
+        .collect()
+}
+
+#[inline]
+pub fn output_error(response: &RequestResponse) -> String {
+    format!("(ERROR:{})", response.error.clone().unwrap_or_default())
+}
+
+// Writes an element containing only text
+#[inline]
+fn xml_element<W: Write>(writer: &mut EventWriter<W>, name: &str, value: &str)
+    -> xml::writer::Result<()> {
+    writer.write(XmlEvent::start_element(name))?;
+    writer.write(XmlEvent::characters(value))?;
+    writer.write(XmlEvent::end_element())
+}
+
+pub fn output_xml<W: Write>(writer: &mut EventWriter<W>, response: &RequestResponse)
+    -> xml::writer::Result<()> {
+    let mut element = XmlEvent::start_element("file")
+        .attr("url", &response.url)
+        .attr("method", response.verb.method());
+    if let Some(method_override) = response.method_override {
+        element = element.attr("method_override", method_override.method());
+    }
+    if let Some(word) = &response.word {
+        element = element.attr("word", word);
+    }
+    if let Some(derived_from) = &response.derived_from {
+        element = element.attr("derived_from", derived_from);
+    }
+    writer.write(element)?;
+    xml_element(writer, "status_code", &response.code.to_string())?;
+    xml_element(writer, "size", &response.content_len.to_string())?;
+    xml_element(writer, "words", &response.words.to_string())?;
+    xml_element(writer, "lines", &response.lines.to_string())?;
+    xml_element(writer, "is_directory", &response.is_directory.to_string())?;
+    xml_element(writer, "is_listable", &response.is_listable.to_string())?;
+    xml_element(writer, "found_from_listable", &response.found_from_listable.to_string())?;
+    xml_element(writer, "redirect_url", &response.redirect_url)?;
+    if let Some(chain) = &response.redirect_chain {
+        writer.write(XmlEvent::start_element("redirect_chain"))?;
+        for url in &chain.urls {
+            xml_element(writer, "url", url)?;
+        }
+        xml_element(writer, "final_code", &chain.final_code.to_string())?;
+        xml_element(writer, "final_size", &chain.final_size.to_string())?;
+        writer.write(XmlEvent::end_element())?;
+    }
+    if !response.parameters.is_empty() {
+        writer.write(XmlEvent::start_element("parameters"))?;
+        for parameter in &response.parameters {
+            writer.write(XmlEvent::start_element("parameter")
+                .attr("name", &parameter.name)
+                .attr("change", parameter.change.name()))?;
+            xml_element(writer, "status_code", &parameter.code.to_string())?;
+            xml_element(writer, "size", &parameter.size.to_string())?;
+            writer.write(XmlEvent::end_element())?;
+        }
+        writer.write(XmlEvent::end_element())?;
+    }
+    writer.write(XmlEvent::start_element("timing"))?;
+    xml_element(writer, "total_ms", &response.timing.total_ms.to_string())?;
+    xml_element(writer, "first_byte_ms", &response.timing.first_byte_ms.to_string())?;
+    xml_element(writer, "connect_ms", &response.timing.connect_ms.to_string())?;
+    writer.write(XmlEvent::end_element())?;
+    writer.write(XmlEvent::start_element("headers"))?;
+    for (name, value) in response.headers.pairs() {
+        writer.write(XmlEvent::start_element("header").attr("name", name))?;
+        writer.write(XmlEvent::characters(value))?;
+        writer.write(XmlEvent::end_element())?;
+    }
+    writer.write(XmlEvent::end_element())?;
+    writer.write(XmlEvent::end_element())
+}
+
+pub fn output_xml_failed<W: Write>(writer: &mut EventWriter<W>, response: &RequestResponse)
+    -> xml::writer::Result<()> {
+    writer.write(XmlEvent::start_element("failed_request").attr("url", &response.url))?;
+    xml_element(writer, "error", &response.error.clone().unwrap_or_default())?;
+    writer.write(XmlEvent::end_element())
+}
+
+pub fn output_xml_backoff<W: Write>(writer: &mut EventWriter<W>, event: &BackoffEvent)
+    -> xml::writer::Result<()> {
+    writer.write(XmlEvent::start_element("backoff").attr("host", &event.host))?;
+    xml_element(writer, "reason", &event.reason)?;
+    xml_element(writer, "pause_seconds", &event.pause.as_secs().to_string())?;
+    writer.write(XmlEvent::end_element())
+}
+
+pub fn output_xml_latency<W: Write>(writer: &mut EventWriter<W>, summary: &LatencySummary)
+    -> xml::writer::Result<()> {
+    writer.write(XmlEvent::start_element("latency").attr("host", &summary.host))?;
+    xml_element(writer, "requests", &summary.requests.to_string())?;
+    xml_element(writer, "average_ms", &summary.average_ms.to_string())?;
+    xml_element(writer, "median_ms", &summary.median_ms.to_string())?;
+    xml_element(writer, "p95_ms", &summary.p95_ms.to_string())?;
+    xml_element(writer, "max_ms", &summary.max_ms.to_string())?;
+    writer.write(XmlEvent::end_element())
+}
+
+pub fn output_xml_metadata<W: Write>(writer: &mut EventWriter<W>, metadata: &ScanMetadata)
+    -> xml::writer::Result<()> {
+    writer.write(XmlEvent::start_element("metadata"))?;
+    xml_element(writer, "dirble_version", &metadata.dirble_version)?;
+    xml_element(writer, "start_time", &metadata.start_time)?;
+    if let Some(end_time) = &metadata.end_time {
+        xml_element(writer, "end_time", end_time)?;
+    }
+
+    let lists = [("targets", "target", &metadata.targets),
+                 ("wordlists", "wordlist", &metadata.wordlists),
+                 ("prefixes", "prefix", &metadata.prefixes),
+                 ("extensions", "extension", &metadata.extensions)];
+    for (list_name, item_name, items) in lists.iter() {
+        writer.write(XmlEvent::start_element(*list_name))?;
+        for item in items.iter() {
+            xml_element(writer, item_name, item)?;
         }
-        _ => {
-            format!("(CODE:{}|SIZE:{:#?})", code_string, response.content_len)
+        writer.write(XmlEvent::end_element())?;
+    }
+
+    // Options are written as name/value pairs from their JSON form,
+    // so that both formats always contain the same options
+    writer.write(XmlEvent::start_element("options"))?;
+    if let Ok(Value::Object(options)) = serde_json::to_value(&metadata.options) {
+        for (name, value) in options {
+            let value = match value {
+                Value::Null => continue,
+                Value::String(value) => value,
+                Value::Array(values) => values.iter()
+                    .map(|value| match value {
+                        Value::String(value) => value.clone(),
+                        value => value.to_string()
+                    })
+                    .collect::<Vec<String>>()
+                    .join(","),
+                value => value.to_string()
+            };
+            writer.write(XmlEvent::start_element("option").attr("name", &name))?;
+            writer.write(XmlEvent::characters(&value))?;
+            writer.write(XmlEvent::end_element())?;
         }
     }
+    writer.write(XmlEvent::end_element())?;
+
+    writer.write(XmlEvent::end_element())
+}
+
+// The form of a result in the JSON report
+#[derive(Serialize)]
+pub struct JsonResult<'a> {
+    url: &'a str,
+    method: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    method_override: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    word: Option<&'a str>,
+    code: u32,
+    size: usize,
+    words: usize,
+    lines: usize,
+    is_directory: bool,
+    is_listable: bool,
+    found_from_listable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    derived_from: Option<&'a str>,
+    redirect_url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redirect_chain: Option<&'a RedirectChain>,
+    timing: &'a RequestTiming,
+    headers: &'a ResponseHeaders,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    parameters: &'a [DiscoveredParameter]
+}
+
+#[derive(Serialize)]
+pub struct JsonFailed<'a> {
+    url: &'a str,
+    error: &'a str
+}
+
+#[derive(Serialize)]
+pub struct JsonBackoff<'a> {
+    host: &'a str,
+    reason: &'a str,
+    pause_seconds: u64
 }
 
 #[inline]
-pub fn output_xml(response: &RequestResponse) -> String {
-    format!("<file url=\"{}\">
-    <status_code>{}</status_code>
-    <size>{}</size>
-    <is_directory>{}</is_directory>
-    <is_listable>{}</is_listable>
-    <found_from_listable>{}</found_from_listable>
-    <redirect_url>{}</redirect_url>
-</file>\n", 
-    response.url,
-    response.code,
-    response.content_len,
-    response.is_directory,
-    response.is_listable,
-    response.found_from_listable,
-    response.redirect_url)
+pub fn output_json(response: &RequestResponse) -> JsonResult<'_> {
+    JsonResult {
+        url: &response.url,
+        method: response.verb.method(),
+        method_override: response.method_override.map(|verb| verb.method()),
+        word: response.word.as_ref().map(String::as_str),
+        code: response.code,
+        size: response.content_len,
+        words: response.words,
+        lines: response.lines,
+        is_directory: response.is_directory,
+        is_listable: response.is_listable,
+        found_from_listable: response.found_from_listable,
+        derived_from: response.derived_from.as_ref().map(String::as_str),
+        redirect_url: &response.redirect_url,
+        redirect_chain: response.redirect_chain.as_ref(),
+        timing: &response.timing,
+        headers: &response.headers,
+        parameters: &response.parameters
+    }
 }
 
 #[inline]
-pub fn output_json(response: &RequestResponse) -> String {
-
-    format!("{{\
-        \"url\": \"{}\", \
-        \"code\": {}, \
-        \"size\": {}, \
-        \"is_directory\": {}, \
-        \"is_listable\": {}, \
-        \"found_from_listable\": {}, \
-        \"redirect_url\": \"{}\"\
-        }}",
-        response.url,
-        response.code,
-        response.content_len,
-        response.is_directory,
-        response.is_listable,
-        response.found_from_listable,
-        response.redirect_url)
+pub fn output_json_failed(response: &RequestResponse) -> JsonFailed<'_> {
+    JsonFailed {
+        url: &response.url,
+        error: match &response.error {
+            Some(error) => error,
+            None => ""
+        }
+    }
+}
+
+#[inline]
+pub fn output_json_backoff(event: &BackoffEvent) -> JsonBackoff<'_> {
+    JsonBackoff {
+        host: &event.host,
+        reason: &event.reason,
+        pause_seconds: event.pause.as_secs()
+    }
 }
@@ -20,6 +20,8 @@
 // relies on the indented string having the correct number of leading
 // spaces.
 
+use xml::writer::EmitterConfig;
+
 #[test]
 fn check_output_indentation() {
     //   super::output_indentation produces a number of spaces based on
@@ -159,35 +161,90 @@ fn check_output_suffix() {
         "Disabling colours hasn't worked properly");
 }
 
+#[test]
+fn check_output_suffix_redirects() {
+    let mut req_response = generate_request_response();
+    req_response.code = 307;
+    req_response.content_len = 0;
+    req_response.redirect_url = "http://example.com/a".into();
+    assert_eq!(
+        super::output_suffix(&req_response, false),
+        "(CODE:307|SIZE:0|DEST:http://example.com/a)",
+        "Output suffix for code 307 invalid");
+
+    req_response.redirect_chain = Some(super::RedirectChain {
+        urls: vec!["http://example.com/a".into(), "http://example.com/b".into()],
+        final_code: 200,
+        final_size: 123
+    });
+    assert_eq!(
+        super::output_suffix(&req_response, false),
+        "(CODE:307|SIZE:0|DEST:http://example.com/a -> http://example.com/b\
+            |FINAL_CODE:200|FINAL_SIZE:123)",
+        "Output suffix for a followed redirect invalid");
+}
+
 #[test]
 fn check_output_xml() {
     // Same as check_output_json below, but with hardcoded XML output.
     let req_response = super::RequestResponse {
         url: "http://example.com".into(),
+        verb: crate::arg_parse::HttpVerb::Get,
+        method_override: None,
+        word: None,
         code: 204,
         content_len: 345,
+        words: 0,
+        lines: 0,
         is_directory: false,
         is_listable: false,
         found_from_listable: true,
+        derived_from: None,
         redirect_url: "https://example.org".into(),
-        parent_depth: 2
+        redirect_chain: None,
+        parent_depth: 2,
+        headers: Default::default(),
+        timing: Default::default(),
+        parameters: Vec::new(),
+        fingerprint: 0,
+        error: None
     };
     // DO NOT change the indentation here, it matches the indentation
     // produced by the XML formatter.
     assert_eq!(
-        super::output_xml(&req_response),
-        "<file url=\"http://example.com\">
-    <status_code>204</status_code>
-    <size>345</size>
-    <is_directory>false</is_directory>
-    <is_listable>false</is_listable>
-    <found_from_listable>true</found_from_listable>
-    <redirect_url>https://example.org</redirect_url>
-</file>
-",
+        xml_string(&req_response),
+        "<file url=\"http://example.com\" method=\"GET\">
+  <status_code>204</status_code>
+  <size>345</size>
+  <words>0</words>
+  <lines>0</lines>
+  <is_directory>false</is_directory>
+  <is_listable>false</is_listable>
+  <found_from_listable>true</found_from_listable>
+  <redirect_url>https://example.org</redirect_url>
+  <timing>
+    <total_ms>0</total_ms>
+    <first_byte_ms>0</first_byte_ms>
+    <connect_ms>0</connect_ms>
+  </timing>
+  <headers />
+</file>",
         "XML format invalid");
 }
 
+#[test]
+fn check_output_xml_escaping() {
+    let mut req_response = generate_request_response();
+    req_response.url = "http://example.com/a\"b&c<d".into();
+    req_response.redirect_url = "http://example.com/?a=1&b=<2>".into();
+
+    let xml = xml_string(&req_response);
+    assert!(xml.starts_with("<file url=\"http://example.com/a&quot;b&amp;c&lt;d\" method=\"GET\">"),
+        "XML attribute not escaped: {}", xml);
+    assert!(xml.contains("<redirect_url>http://example.com/?a=1&amp;b=&lt;2&gt;</redirect_url>"),
+        "XML text not escaped: {}", xml);
+}
+
 #[test]
 fn check_output_json() {
     // This doesn't use the generate_request_response function because
@@ -195,43 +252,348 @@ fn check_output_json() {
     // hardcoded.
     let req_response = super::RequestResponse {
         url: "http://example.com".into(),
+        verb: crate::arg_parse::HttpVerb::Get,
+        method_override: None,
+        word: None,
         code: 200,
         content_len: 350,
+        words: 0,
+        lines: 0,
         is_directory: false,
         is_listable: true,
         found_from_listable: false,
+        derived_from: None,
         redirect_url: "https://example.org".into(),
-        parent_depth: 0
+        redirect_chain: None,
+        parent_depth: 0,
+        headers: Default::default(),
+        timing: Default::default(),
+        parameters: Vec::new(),
+        fingerprint: 0,
+        error: None
     };
-    let json = super::output_json(&req_response);
+    let json = serde_json::to_string(&super::output_json(&req_response)).unwrap();
 
     assert_eq!(
         json,
         "{\
-        \"url\": \"http://example.com\", \
-            \"code\": 200, \
-            \"size\": 350, \
-            \"is_directory\": false, \
-            \"is_listable\": true, \
-            \"found_from_listable\": false, \
-            \"redirect_url\": \"https://example.org\"\
+        \"url\":\"http://example.com\",\
+            \"method\":\"GET\",\
+            \"code\":200,\
+            \"size\":350,\
+            \"words\":0,\
+            \"lines\":0,\
+            \"is_directory\":false,\
+            \"is_listable\":true,\
+            \"found_from_listable\":false,\
+            \"redirect_url\":\"https://example.org\",\
+            \"timing\":{\
+                \"total_ms\":0,\
+                \"first_byte_ms\":0,\
+                \"connect_ms\":0\
+            },\
+            \"headers\":{}\
             }\
             ",
             "JSON output appears invalid!");
 }
 
+#[test]
+fn check_output_redirect_chain() {
+    let mut req_response = generate_request_response();
+    req_response.code = 301;
+    req_response.redirect_chain = Some(super::RedirectChain {
+        urls: vec!["https://example.org".into()],
+        final_code: 404,
+        final_size: 10
+    });
+
+    let json = serde_json::to_string(&super::output_json(&req_response)).unwrap();
+    assert!(json.ends_with("\"redirect_chain\":{\
+        \"urls\":[\"https://example.org\"],\
+        \"final_code\":404,\
+        \"final_size\":10},\
+        \"timing\":{\"total_ms\":0,\"first_byte_ms\":0,\"connect_ms\":0},\
+        \"headers\":{}}"),
+        "JSON redirect chain invalid: {}", json);
+
+    let xml = xml_string(&req_response);
+    assert!(xml.ends_with("  <redirect_chain>
+    <url>https://example.org</url>
+    <final_code>404</final_code>
+    <final_size>10</final_size>
+  </redirect_chain>
+  <timing>
+    <total_ms>0</total_ms>
+    <first_byte_ms>0</first_byte_ms>
+    <connect_ms>0</connect_ms>
+  </timing>
+  <headers />
+</file>"),
+        "XML redirect chain invalid: {}", xml);
+}
+
+#[test]
+fn check_output_verb() {
+    let mut req_response = generate_request_response();
+    req_response.url = "http://example.com/admin".into();
+    req_response.code = 403;
+    assert_eq!(super::output_url(&req_response), "http://example.com/admin ",
+        "GET shouldn't be shown in the output");
+
+    req_response.verb = crate::arg_parse::HttpVerb::Put;
+    req_response.method_override = Some(crate::arg_parse::HttpVerb::Get);
+    assert_eq!(super::output_url(&req_response), "PUT http://example.com/admin ",
+        "Output url for a PUT invalid");
+    assert_eq!(super::output_suffix(&req_response, false),
+        "(CODE:403|SIZE:350|METHOD_OVERRIDE:GET)",
+        "Output suffix for a method override invalid");
+
+    let json = serde_json::to_string(&super::output_json(&req_response)).unwrap();
+    assert!(json.starts_with("{\"url\":\"http://example.com/admin\",\
+        \"method\":\"PUT\",\"method_override\":\"GET\",\"code\":403,"),
+        "JSON verb invalid: {}", json);
+
+    let xml = xml_string(&req_response);
+    assert!(xml.starts_with("<file url=\"http://example.com/admin\" method=\"PUT\" \
+        method_override=\"GET\">"),
+        "XML verb invalid: {}", xml);
+}
+
+#[test]
+fn check_output_derived_from() {
+    let mut req_response = generate_request_response();
+    req_response.url = "http://example.com/config.php.bak".into();
+    req_response.derived_from = Some("http://example.com/config.php".into());
+    assert_eq!(super::output_suffix(&req_response, false),
+        "(CODE:200|SIZE:350|DERIVED_FROM:http://example.com/config.php)",
+        "Output suffix for a backup invalid");
+
+    let json = serde_json::to_string(&super::output_json(&req_response)).unwrap();
+    assert!(json.contains("\"found_from_listable\":false,\
+        \"derived_from\":\"http://example.com/config.php\","),
+        "JSON derived from invalid: {}", json);
+
+    let xml = xml_string(&req_response);
+    assert!(xml.starts_with("<file url=\"http://example.com/config.php.bak\" method=\"GET\" \
+        derived_from=\"http://example.com/config.php\">"),
+        "XML derived from invalid: {}", xml);
+}
+
+#[test]
+fn check_output_headers() {
+    let mut req_response = generate_request_response();
+    req_response.headers.content_type = Some("text/html".into());
+    req_response.headers.set_cookie = vec!["a=1".into(), "b=2".into()];
+
+    assert_eq!(
+        super::output_headers(&req_response),
+        "\n    Content-Type: text/html\n    Set-Cookie: a=1\n    Set-Cookie: b=2",
+        "Header output invalid");
+
+    let json = serde_json::to_string(&super::output_json(&req_response)).unwrap();
+    assert!(json.ends_with("\"headers\":{\
+        \"content_type\":\"text/html\",\
+        \"set_cookie\":[\"a=1\",\"b=2\"]}}"),
+        "JSON headers invalid: {}", json);
+
+    let xml = xml_string(&req_response);
+    assert!(xml.ends_with("  <headers>
+    <header name=\"Content-Type\">text/html</header>
+    <header name=\"Set-Cookie\">a=1</header>
+    <header name=\"Set-Cookie\">b=2</header>
+  </headers>
+</file>"),
+        "XML headers invalid: {}", xml);
+}
+
+#[test]
+fn check_output_json_escaping() {
+    let mut req_response = generate_request_response();
+    req_response.url = "http://example.com/a\"b\\c".into();
+
+    let json = serde_json::to_string(&super::output_json(&req_response)).unwrap();
+    assert!(json.contains("\"url\":\"http://example.com/a\\\"b\\\\c\""),
+        "JSON string not escaped: {}", json);
+
+    let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed["url"], "http://example.com/a\"b\\c");
+}
+
+#[test]
+fn check_output_xml_list_options() {
+    let global_opts = crate::arg_parse::parse_args(
+        vec!["dirble", "http://example.com", "--verb", "GET,POST"]
+            .into_iter().map(String::from).collect());
+    let metadata = crate::output::ScanMetadata::new(&global_opts);
+
+    let mut output = Vec::new();
+    {
+        let mut writer = EmitterConfig::new()
+            .write_document_declaration(false)
+            .create_writer(&mut output);
+        super::output_xml_metadata(&mut writer, &metadata).unwrap();
+    }
+    let xml = String::from_utf8(output).unwrap();
+    assert!(xml.contains("<option name=\"http_verbs\">GET,POST</option>"),
+        "List option has quoted items: {}", xml);
+}
+
+#[test]
+fn check_output_jsonl_events() {
+    use crate::output::ScanEvent;
+    use crate::validator_thread::{TargetValidator, ValidatorAlert};
+
+    let mut req_response = generate_request_response();
+    req_response.content_len = 12;
+    assert_eq!(
+        serde_json::to_string(&ScanEvent::from_response(&req_response)).unwrap(),
+        "{\
+        \"type\":\"response\",\
+            \"url\":\"http://example.com\",\
+            \"method\":\"GET\",\
+            \"code\":200,\
+            \"size\":12,\
+            \"words\":0,\
+            \"lines\":0,\
+            \"is_directory\":false,\
+            \"is_listable\":false,\
+            \"found_from_listable\":false,\
+            \"redirect_url\":\"https://example.org\",\
+            \"timing\":{\
+                \"total_ms\":0,\
+                \"first_byte_ms\":0,\
+                \"connect_ms\":0\
+            },\
+            \"headers\":{},\
+            \"parent_depth\":2\
+            }",
+        "JSON Lines response appears invalid!");
+
+    // Failed requests are written as their own type
+    req_response.error = Some(String::from("Timeout was reached"));
+    assert_eq!(
+        serde_json::to_string(&ScanEvent::from_response(&req_response)).unwrap(),
+        "{\"type\":\"failed_request\",\"url\":\"http://example.com\",\
+            \"error\":\"Timeout was reached\"}");
+
+    let validator = ScanEvent::Validator {
+        url: String::from("http://example.com/"),
+        method: "GET",
+        prefix: String::new(),
+        extension: String::from(".php"),
+        validator: TargetValidator::new(403, Some(120), None, None, None,
+            Some(ValidatorAlert::Code403))
+    };
+    assert_eq!(
+        serde_json::to_string(&validator).unwrap(),
+        "{\
+        \"type\":\"validator\",\
+            \"url\":\"http://example.com/\",\
+            \"method\":\"GET\",\
+            \"prefix\":\"\",\
+            \"extension\":\".php\",\
+            \"validator\":{\
+                \"response_code\":403,\
+                \"response_len\":120,\
+                \"diff_response_len\":null,\
+                \"redirect_url\":null,\
+                \"fingerprint\":null,\
+                \"validator_alert\":\"Code403\"\
+            }\
+            }",
+        "JSON Lines validator appears invalid!");
+
+    let changed = ScanEvent::ValidatorChanged {
+        url: String::from("http://example.com/"),
+        method: "POST",
+        prefix: String::new(),
+        extension: String::new(),
+        previous: TargetValidator::new(404, None, None, None, None, None),
+        validator: TargetValidator::new(302, None, None,
+            Some(String::from("http://example.com/login")), None, None)
+    };
+    let changed = serde_json::to_value(&changed).unwrap();
+    assert_eq!(changed["type"], "validator_changed");
+    assert_eq!(changed["method"], "POST");
+    assert_eq!(changed["previous"]["response_code"], 404);
+    assert_eq!(changed["validator"]["redirect_url"], "http://example.com/login");
+
+    let skipped = ScanEvent::SkippedDirectory {
+        url: String::from("http://example.com/admin/"),
+        reason: String::from("directory returns 401")
+    };
+    assert_eq!(
+        serde_json::to_string(&skipped).unwrap(),
+        "{\"type\":\"skipped_directory\",\"url\":\"http://example.com/admin/\",\
+            \"reason\":\"directory returns 401\"}");
+}
+
+#[test]
+fn check_output_jsonl_file() {
+    use std::io::{LineWriter, Read, Seek, SeekFrom};
+    use crate::output::{write_jsonl, ScanEvent};
+
+    // Each event is written as a single line, even if it contains newlines
+    let mut file = tempfile::tempfile().unwrap();
+    {
+        let mut writer = LineWriter::new(file.try_clone().unwrap());
+        write_jsonl(&mut writer, &ScanEvent::SkippedDirectory {
+            url: String::from("http://example.com/a/"),
+            reason: String::from("errored too often\nduring validation")
+        });
+        write_jsonl(&mut writer, &ScanEvent::from_response(&generate_request_response()));
+    }
+
+    let mut contents = String::new();
+    file.seek(SeekFrom::Start(0)).unwrap();
+    file.read_to_string(&mut contents).unwrap();
+    assert!(contents.ends_with("\n"));
+
+    let types: Vec<String> = contents.lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["type"]
+            .as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(types, vec!["skipped_directory", "response"]);
+}
+
+// Renders a single file element without the XML declaration
+fn xml_string(response: &super::RequestResponse) -> String {
+    let mut output = Vec::new();
+    {
+        let mut writer = EmitterConfig::new()
+            .perform_indent(true)
+            .write_document_declaration(false)
+            .create_writer(&mut output);
+        super::output_xml(&mut writer, response).unwrap();
+    }
+    String::from_utf8(output).unwrap()
+}
+
 #[inline]
 fn generate_request_response() -> super::RequestResponse {
     // Generate a RequestResponse object with sane default settings to
     // simplify the testing routines.
     super::RequestResponse {
         url: "http://example.com".into(),
+        verb: crate::arg_parse::HttpVerb::Get,
+        method_override: None,
+        word: None,
         code: 200,
         content_len: 350,
+        words: 0,
+        lines: 0,
         is_directory: false,
         is_listable: false,
         found_from_listable: false,
+        derived_from: None,
         redirect_url: "https://example.org".into(),
-        parent_depth: 2 // Depth is number of slashes, 2 for http://
+        redirect_chain: None,
+        parent_depth: 2, // Depth is number of slashes, 2 for http://
+        headers: Default::default(),
+        timing: Default::default(),
+        parameters: Vec::new(),
+        fingerprint: 0,
+        error: None
     }
 }
@@ -17,21 +17,55 @@
 
 use crate::arg_parse;
 use std::{
-    sync::{Arc, mpsc}
+    sync::{Arc, mpsc, atomic::{AtomicBool, Ordering}}
 };
 use crate::request;
-use crate::output;
+use crate::output::{self, ScanEvent};
+use crate::rate_limit::RateLimiter;
 
 pub fn output_thread(rx: mpsc::Receiver<request::RequestResponse>,
-    global_opts: Arc<arg_parse::GlobalOpts>, file_handles: output::FileHandles)
+    event_rx: mpsc::Receiver<ScanEvent>,
+    global_opts: Arc<arg_parse::GlobalOpts>, mut file_handles: output::FileHandles,
+    rate_limiter: Arc<RateLimiter>, resumed: Vec<request::RequestResponse>,
+    stopping: Arc<AtomicBool>, metadata: output::ScanMetadata)
 {
-    let mut response_list: Vec<request::RequestResponse> = Vec::new();
+    // Responses found before the scan was resumed are included in the report
+    let (mut failed_list, mut response_list): (Vec<request::RequestResponse>, Vec<request::RequestResponse>) =
+        resumed.into_iter().partition(|response| response.error.is_some());
+
+    // The JSON Lines output is written as the scan runs rather than in the report
+    let mut jsonl_file = file_handles.jsonl_file.take();
+    if let Some(handle) = &mut jsonl_file {
+        for response in response_list.iter().chain(failed_list.iter()) {
+            output::write_jsonl(handle, &ScanEvent::from_response(response));
+        }
+    }
 
     loop {
+        if let Ok(event) = event_rx.try_recv() {
+            if let Some(handle) = &mut jsonl_file {
+                output::write_jsonl(handle, &event);
+            }
+        }
+
         if let Ok(response) = rx.try_recv() {
             if response.url == "MAIN ENDING" {
+                // Every event has been sent before main ends
+                for event in event_rx.try_iter() {
+                    if let Some(handle) = &mut jsonl_file {
+                        output::write_jsonl(handle, &event);
+                    }
+                }
                 break; 
             }
+            if let Some(handle) = &mut jsonl_file {
+                output::write_jsonl(handle, &ScanEvent::from_response(&response));
+            }
+            // The error has already been printed when the request failed
+            if response.error.is_some() {
+                failed_list.push(response);
+                continue;
+            }
             if !global_opts.silent {
                 match output::print_response(&response, global_opts.clone(),
                     false, false, global_opts.is_terminal && !global_opts.no_color) {
@@ -43,6 +77,9 @@ pub fn output_thread(rx: mpsc::Receiver<request::RequestResponse>,
         }
     }
 
-    output::print_report(response_list, global_opts.clone(), file_handles);
+    output::print_report(response_list, failed_list,
+        rate_limiter.backoff_events(), rate_limiter.latency_summaries(),
+        stopping.load(Ordering::SeqCst),
+        metadata, global_opts.clone(), file_handles);
 
 }
\ No newline at end of file
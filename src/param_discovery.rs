@@ -0,0 +1,145 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::arg_parse::GlobalOpts;
+use crate::content_parse;
+use crate::request::{DiscoveredParameter, ParameterChange, Request, RequestResponse};
+use crate::request_engine::RequestEngine;
+use crate::validator_thread::rand_string;
+use percent_encoding::{utf8_percent_encode, QUERY_ENCODE_SET};
+
+#[cfg(test)]
+mod tests;
+
+// The value given to every parameter which is tried
+const PARAMETER_VALUE: &str = "1";
+
+// Requests a found file again with batches of parameter names added to
+// its query string, and returns the parameters which changed the response.
+// Each batch is compared to a baseline made with the same number of random
+// names, and batches which changed it are split in half until the single
+// parameters responsible are found
+pub fn discover(engine: &mut RequestEngine, global_opts: &GlobalOpts, request: &Request)
+    -> Vec<DiscoveredParameter> {
+    let batch_size = global_opts.parameter_batch as usize;
+
+    // Pages which change by themselves can't be compared against, so a
+    // second baseline is made to check the first
+    let mut baselines = fetch_all(engine, request, vec![random_names(batch_size),
+        random_names(batch_size)]);
+    let (check, baseline) = (baselines.pop().unwrap(), baselines.pop().unwrap());
+    if baseline.error.is_some() || find_change(&baseline, &check).is_some() {
+        if global_opts.verbose {
+            println!("Skipping parameter discovery for {} as it changes between requests",
+                request.url);
+        }
+        return Vec::new();
+    }
+
+    let mut parameters = Vec::new();
+    let mut changed: Vec<(Vec<String>, RequestResponse)> = Vec::new();
+
+    // The batches are made a few at a time so the engine isn't flooded
+    let batches: Vec<Vec<String>> = global_opts.parameters.chunks(batch_size)
+        .map(|batch| batch.to_vec())
+        .collect();
+    for group in batches.chunks(global_opts.concurrency as usize) {
+        let responses = fetch_all(engine, request, group.to_vec());
+        changed.extend(group.iter().cloned().zip(responses)
+            .filter(|(_, response)| find_change(&baseline, response).is_some()));
+    }
+
+    // Narrow each changed batch down to the parameters which changed it
+    while let Some((names, response)) = changed.pop() {
+        if names.len() == 1 {
+            parameters.push(DiscoveredParameter {
+                name: names[0].clone(),
+                change: find_change(&baseline, &response).unwrap(),
+                code: response.code,
+                size: response.content_len
+            });
+            continue;
+        }
+
+        let (left, right) = names.split_at(names.len() / 2);
+        let halves = vec![left.to_vec(), right.to_vec()];
+        let responses = fetch_all(engine, request, halves.clone());
+        changed.extend(halves.into_iter().zip(responses)
+            .filter(|(_, response)| find_change(&baseline, response).is_some()));
+    }
+
+    parameters.sort_by(|a, b| a.name.cmp(&b.name));
+    parameters
+}
+
+// Gives what changed between the baseline and a response, responses with
+// a similar body can differ in size by as much as the length of the longer
+// query string, as pages often echo the parameters they are given
+pub fn find_change(baseline: &RequestResponse, response: &RequestResponse)
+    -> Option<ParameterChange> {
+    if response.error.is_some() {
+        return None;
+    }
+
+    if response.code != baseline.code {
+        Some(ParameterChange::Code)
+    }
+    else if !content_parse::is_similar(response.fingerprint, baseline.fingerprint) {
+        Some(ParameterChange::Body)
+    }
+    else {
+        let allowance = query_len(&response.url).max(query_len(&baseline.url));
+        let difference = (response.content_len as i64 - baseline.content_len as i64).abs();
+        if difference as usize > allowance { Some(ParameterChange::Size) } else { None }
+    }
+}
+
+// Adds each of the parameters to the url, after any query it already has
+pub fn url_with_parameters(url: &str, names: &[String]) -> String {
+    let query: Vec<String> = names.iter()
+        .map(|name| format!("{}={}", utf8_percent_encode(name, QUERY_ENCODE_SET),
+            PARAMETER_VALUE))
+        .collect();
+    let separator = if url.contains('?') { "&" } else { "?" };
+
+    format!("{}{}{}", url, separator, query.join("&"))
+}
+
+// Makes a request with each set of parameters concurrently, the responses
+// are returned in the same order
+fn fetch_all(engine: &mut RequestEngine, request: &Request, batches: Vec<Vec<String>>)
+    -> Vec<RequestResponse> {
+    let tokens: Vec<usize> = batches.iter()
+        .map(|names| engine.submit_request(Request {
+            url: url_with_parameters(&request.url, names),
+            ..request.clone()
+        }))
+        .collect();
+
+    tokens.into_iter()
+        .map(|token| engine.wait_for(token))
+        .collect()
+}
+
+fn random_names(count: usize) -> Vec<String> {
+    (0..count).map(|_| rand_string(8).to_lowercase()).collect()
+}
+
+#[inline]
+fn query_len(url: &str) -> usize {
+    url.find('?').map(|index| url.len() - index).unwrap_or(0)
+}
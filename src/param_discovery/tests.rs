@@ -0,0 +1,60 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::{find_change, url_with_parameters};
+use crate::content_parse::simhash;
+use crate::request::{fabricate_request_response, ParameterChange};
+
+#[test]
+fn parameters_are_added_to_urls() {
+    let names = vec![String::from("debug"), String::from("a b")];
+    assert_eq!(url_with_parameters("http://example.com/export.php", &names),
+        "http://example.com/export.php?debug=1&a%20b=1");
+    assert_eq!(url_with_parameters("http://example.com/export.php?id=2", &names[0..1]),
+        "http://example.com/export.php?id=2&debug=1");
+}
+
+#[test]
+fn changes_are_detected() {
+    let body = "Export your records below, choose a format and press the button to download them";
+    let mut baseline = fabricate_request_response(
+        String::from("http://example.com/export.php?abc=1"), false, false);
+    baseline.code = 200;
+    baseline.content_len = 1000;
+    baseline.fingerprint = simhash(body, &baseline.url);
+
+    // Echoing the parameters back only changes the size a little
+    let mut response = baseline.clone();
+    response.url = String::from("http://example.com/export.php?debug=1");
+    response.content_len = 1004;
+    assert_eq!(find_change(&baseline, &response), None);
+
+    response.content_len = 1500;
+    assert_eq!(find_change(&baseline, &response), Some(ParameterChange::Size));
+
+    response.fingerprint = simhash(
+        "Fatal error: Uncaught exception in /var/www/lib/db.php on line 12, stack trace follows",
+        &response.url);
+    assert_eq!(find_change(&baseline, &response), Some(ParameterChange::Body));
+
+    response.code = 500;
+    assert_eq!(find_change(&baseline, &response), Some(ParameterChange::Code));
+
+    // Failed requests aren't counted as changes
+    response.error = Some(String::from("Timeout was reached"));
+    assert_eq!(find_change(&baseline, &response), None);
+}
@@ -0,0 +1,432 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ops::Bound::{Excluded, Unbounded},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use serde::Serialize;
+use crate::request;
+
+#[cfg(test)]
+mod tests;
+
+// The initial delay between requests to a host after it starts
+// rejecting requests, this is doubled each time the host complains again
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// Once the delay drops below this the host is scanned at full speed again
+const MIN_BACKOFF: Duration = Duration::from_millis(20);
+// Upper bound on how long a Retry-After header can pause a host for
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(600);
+// The number of consecutive errors before a host is backed off from
+const ERRORS_BEFORE_BACKOFF: u32 = 2;
+// Response times are kept to this many significant figures, so that the
+// number kept for each host stays small however long the scan runs
+const LATENCY_PRECISION: u64 = 1000;
+
+// A token bucket holding a single token, which is refilled at the given
+// rate. Callers reserve the next free slot so that requests are spread
+// evenly rather than sent in bursts. Slots can be reserved ahead of time,
+// and a later caller is given any free gap before them
+struct Bucket {
+    interval: Duration,
+    start: Instant,
+    reserved: BTreeSet<Instant>,
+}
+
+impl Bucket {
+    fn new(interval: Duration, start: Instant) -> Bucket {
+        Bucket {
+            interval,
+            start,
+            reserved: BTreeSet::new(),
+        }
+    }
+
+    // Reserves the first free slot in the bucket which starts no earlier
+    // than the given time and returns when it starts
+    fn reserve(&mut self, earliest: Instant) -> Instant {
+        // Slots which have passed can't clash with any new slot
+        if let Some(passed) = Instant::now().checked_sub(self.interval) {
+            self.reserved = self.reserved.split_off(&passed);
+        }
+
+        let mut slot = if self.start > earliest { self.start } else { earliest };
+        let clashing = match slot.checked_sub(self.interval) {
+            Some(from) => self.reserved.range((Excluded(from), Unbounded)),
+            None => self.reserved.range(..),
+        };
+        for reserved in clashing {
+            if *reserved >= slot + self.interval {
+                break;
+            }
+            slot = *reserved + self.interval;
+        }
+
+        self.reserved.insert(slot);
+        slot
+    }
+}
+
+// The limits currently applied to a single host
+struct HostState {
+    limit: Option<Bucket>,
+    backoff: Option<Bucket>,
+    paused_until: Option<Instant>,
+    // When the latest pause began, responses to requests sent before
+    // then were already in flight and don't affect the back-off
+    paused_at: Option<Instant>,
+    consecutive_errors: u32,
+    latencies: Latencies,
+}
+
+// The response times of a host, as totals and a histogram of the times
+// in milliseconds rounded to three significant figures
+#[derive(Default)]
+struct Latencies {
+    count: usize,
+    total_ms: u64,
+    max_ms: u64,
+    histogram: BTreeMap<u64, usize>,
+}
+
+impl Latencies {
+    fn record(&mut self, total_ms: u64) {
+        self.count += 1;
+        self.total_ms += total_ms;
+        if total_ms > self.max_ms { self.max_ms = total_ms; }
+
+        let mut scale = 1;
+        while total_ms / scale >= LATENCY_PRECISION {
+            scale *= 10;
+        }
+        *self.histogram.entry(total_ms / scale * scale).or_insert(0) += 1;
+    }
+
+    // The time the given percent of responses were at least as fast as,
+    // taken from the sorted times in the same way as from a list of them
+    fn percentile(&self, percent: usize) -> u64 {
+        let index = (self.count - 1) * percent / 100;
+        let mut seen = 0;
+        for (time, count) in &self.histogram {
+            seen += count;
+            if seen > index {
+                return *time;
+            }
+        }
+        self.max_ms
+    }
+}
+
+// Record of a host being backed off from, included in the final report
+#[derive(Clone, Debug)]
+pub struct BackoffEvent {
+    pub host: String,
+    pub reason: String,
+    pub pause: Duration,
+}
+
+// Summary of how long a host took to respond, included in the final report
+#[derive(Clone, Debug, Serialize)]
+pub struct LatencySummary {
+    pub host: String,
+    pub requests: usize,
+    pub average_ms: u64,
+    pub median_ms: u64,
+    pub p95_ms: u64,
+    pub max_ms: u64,
+}
+
+// Limits the rate of requests across every thread, optionally also
+// limiting the rate of requests to each individual host. Hosts which
+// start rejecting requests or timing out are paused and then slowly
+// ramped back up to full speed
+pub struct RateLimiter {
+    global: Option<Mutex<Bucket>>,
+    host_interval: Option<Duration>,
+    hosts: Mutex<HashMap<String, HostState>>,
+    events: Mutex<Vec<BackoffEvent>>,
+    verbose: bool,
+}
+
+impl RateLimiter {
+    // Rates are given in requests per second, None disables that limit
+    pub fn new(global_rate: Option<f64>, host_rate: Option<f64>,
+               verbose: bool) -> RateLimiter {
+        RateLimiter {
+            global: global_rate.map(|rate|
+                Mutex::new(Bucket::new(rate_interval(rate), Instant::now()))),
+            host_interval: host_rate.map(rate_interval),
+            hosts: Mutex::new(HashMap::new()),
+            events: Mutex::new(Vec::new()),
+            verbose,
+        }
+    }
+
+    // Reserves the next slot a request to the given url is allowed to be
+    // made in and returns when it starts, which may be now
+    pub fn reserve(&self, url: &str) -> Instant {
+        let now = Instant::now();
+        let mut slot = now;
+
+        {
+            let mut hosts = self.hosts.lock().unwrap();
+            let host = self.host_state(&mut hosts, url, now);
+
+            if let Some(limit) = &mut host.limit {
+                let host_slot = limit.reserve(now);
+                if host_slot > slot { slot = host_slot; }
+            }
+            if let Some(backoff) = &mut host.backoff {
+                let backoff_slot = backoff.reserve(now);
+                if backoff_slot > slot { slot = backoff_slot; }
+            }
+        }
+
+        // The global slot is reserved from when the host allows the
+        // request, so a request waiting on its host doesn't hold a
+        // global slot which passes before it is sent
+        if let Some(global) = &self.global {
+            slot = global.lock().unwrap().reserve(slot);
+        }
+
+        slot
+    }
+
+    // Updates the state of the response's host based on the response,
+    // backing off if the host is rejecting requests or erroring and
+    // ramping back up if it is responding normally. Takes the time the
+    // request was sent
+    pub fn record_response(&self, url: &str, code: u32,
+                           retry_after: Option<Duration>, sent_at: Instant) {
+        let now = Instant::now();
+        let mut hosts = self.hosts.lock().unwrap();
+        let host = self.host_state(&mut hosts, url, now);
+
+        // Responses to requests made before a pause started will keep
+        // arriving during it, and may have timed out while the host was
+        // paused, so these don't affect the back-off or count as errors
+        let paused = host.paused_until.map_or(false, |paused_until| paused_until > now);
+        let sent_before_pause = host.paused_at.map_or(false, |paused_at| sent_at < paused_at);
+        if paused || sent_before_pause {
+            return;
+        }
+
+        let reason = match code {
+            0 => {
+                host.consecutive_errors += 1;
+                if host.consecutive_errors < ERRORS_BEFORE_BACKOFF {
+                    return;
+                }
+                format!("{} consecutive errors", host.consecutive_errors)
+            }
+            429 => {
+                host.consecutive_errors = 0;
+                String::from("429 Too Many Requests")
+            }
+            503 if retry_after.is_some() => {
+                host.consecutive_errors = 0;
+                String::from("503 Service Unavailable")
+            }
+            _ => {
+                host.consecutive_errors = 0;
+
+                // Each normal response shortens the delay, until it's
+                // small enough to go back to full speed
+                let mut recovered = false;
+                if let Some(backoff) = &mut host.backoff {
+                    backoff.interval = backoff.interval * 3 / 4;
+                    recovered = backoff.interval < MIN_BACKOFF;
+                }
+                if recovered {
+                    host.backoff = None;
+                    if self.verbose {
+                        println!("Resuming full speed scanning of {}",
+                            request::url_host(url));
+                    }
+                }
+                return;
+            }
+        };
+
+        let interval = match &host.backoff {
+            Some(backoff) => {
+                let doubled = backoff.interval * 2;
+                if doubled > MAX_BACKOFF { MAX_BACKOFF } else { doubled }
+            }
+            None => INITIAL_BACKOFF
+        };
+
+        let pause = match retry_after {
+            Some(retry_after) if retry_after > MAX_RETRY_AFTER => MAX_RETRY_AFTER,
+            Some(retry_after) => retry_after,
+            None => interval
+        };
+
+        host.backoff = Some(Bucket::new(interval, now + pause));
+        host.paused_until = Some(now + pause);
+        host.paused_at = Some(now);
+
+        let host_name = request::url_host(url);
+        if self.verbose {
+            println!("Backing off from {} for {}s after {}, then sending one \
+                request every {}ms", host_name, pause.as_secs(), reason,
+                interval.as_millis());
+        }
+
+        self.events.lock().unwrap().push(BackoffEvent {
+            host: host_name,
+            reason,
+            pause,
+        });
+    }
+
+    // The number of requests in a row to the url's host which errored
+    pub fn consecutive_errors(&self, url: &str) -> u32 {
+        let mut hosts = self.hosts.lock().unwrap();
+        self.host_state(&mut hosts, url, Instant::now()).consecutive_errors
+    }
+
+    // Records how long a response from the url's host took
+    pub fn record_latency(&self, url: &str, total_ms: u64) {
+        let mut hosts = self.hosts.lock().unwrap();
+        self.host_state(&mut hosts, url, Instant::now()).latencies.record(total_ms);
+    }
+
+    // A summary of the response times of each host which responded,
+    // sorted by host
+    pub fn latency_summaries(&self) -> Vec<LatencySummary> {
+        let hosts = self.hosts.lock().unwrap();
+        let mut summaries: Vec<LatencySummary> = hosts.iter()
+            .filter(|(_, host)| host.latencies.count != 0)
+            .map(|(host_name, host)| {
+                let latencies = &host.latencies;
+                LatencySummary {
+                    host: host_name.clone(),
+                    requests: latencies.count,
+                    average_ms: latencies.total_ms / latencies.count as u64,
+                    median_ms: latencies.percentile(50),
+                    p95_ms: latencies.percentile(95),
+                    max_ms: latencies.max_ms,
+                }
+            })
+            .collect();
+
+        summaries.sort_by(|a, b| a.host.cmp(&b.host));
+        summaries
+    }
+
+    // Every back-off which happened during the scan
+    pub fn backoff_events(&self) -> Vec<BackoffEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    fn host_state<'a>(&self, hosts: &'a mut HashMap<String, HostState>,
+                      url: &str, now: Instant) -> &'a mut HostState {
+        let host_interval = self.host_interval;
+        hosts.entry(request::url_host(url))
+            .or_insert_with(|| HostState {
+                limit: host_interval.map(|interval| Bucket::new(interval, now)),
+                backoff: None,
+                paused_until: None,
+                paused_at: None,
+                consecutive_errors: 0,
+                latencies: Latencies::default(),
+            })
+    }
+}
+
+// Parses the value of a Retry-After header, which is either a number
+// of seconds or a date in the IMF-fixdate format,
+// e.g. "Wed, 21 Oct 2015 07:28:00 GMT"
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if parts.len() != 6 || parts[5] != "GMT" {
+        return None;
+    }
+
+    let day = parts[1].parse::<i64>().ok()?;
+    let month = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug",
+                 "Sep", "Oct", "Nov", "Dec"]
+        .iter().position(|month| *month == parts[2])? as i64 + 1;
+    let year = parts[3].parse::<i64>().ok()?;
+
+    let time: Vec<i64> = parts[4].split(":")
+        .filter_map(|part| part.parse::<i64>().ok())
+        .collect();
+    if time.len() != 3 {
+        return None;
+    }
+
+    let retry_at = days_from_civil(year, month, day) * 86400
+        + time[0] * 3600 + time[1] * 60 + time[2];
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+
+    if retry_at > now {
+        Some(Duration::from_secs((retry_at - now) as u64))
+    }
+    else {
+        Some(Duration::from_secs(0))
+    }
+}
+
+// Number of days between the unix epoch and the given date, see
+// http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[inline]
+fn rate_interval(rate: f64) -> Duration {
+    Duration::from_nanos((1_000_000_000.0 / rate) as u64)
+}
+
+// Parses a rate given as "50/s", "600/m" or "36000/h" into requests
+// per second. A bare number is taken to be per second
+pub fn parse_rate(value: &str) -> Result<f64, String> {
+    let (count, per_seconds) = match value.find('/') {
+        Some(index) => {
+            let per_seconds = match &value[index+1..] {
+                "s" => 1.0,
+                "m" => 60.0,
+                "h" => 3600.0,
+                _ => return Err(String::from(
+                        "The rate must be given per second (/s), minute (/m) or hour (/h)"))
+            };
+            (&value[..index], per_seconds)
+        },
+        None => (value, 1.0)
+    };
+
+    match count.parse::<u32>() {
+        Ok(count) if count > 0 => Ok(count as f64 / per_seconds),
+        _ => Err(String::from("The rate must be a positive integer number of requests"))
+    }
+}
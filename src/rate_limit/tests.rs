@@ -0,0 +1,315 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::request;
+use super::{days_from_civil, parse_rate, parse_retry_after, Bucket, Latencies, RateLimiter,
+    ERRORS_BEFORE_BACKOFF, INITIAL_BACKOFF, MAX_BACKOFF, MAX_RETRY_AFTER, MIN_BACKOFF};
+
+const URL: &str = "http://example.com/admin";
+
+// The delay between requests to the test host, if it is backed off from
+fn backoff_interval(limiter: &RateLimiter) -> Option<Duration> {
+    let hosts = limiter.hosts.lock().unwrap();
+    hosts[&request::url_host(URL)].backoff.as_ref().map(|backoff| backoff.interval)
+}
+
+// Ends the pause of the test host, as if it had run out
+fn end_pause(limiter: &RateLimiter) {
+    let mut hosts = limiter.hosts.lock().unwrap();
+    hosts.get_mut(&request::url_host(URL)).unwrap().paused_until = None;
+}
+
+#[test]
+fn rates_are_parsed() {
+    assert_eq!(parse_rate("50/s"), Ok(50.0));
+    assert_eq!(parse_rate("600/m"), Ok(10.0));
+    assert_eq!(parse_rate("36000/h"), Ok(10.0));
+    assert_eq!(parse_rate("1/m"), Ok(1.0 / 60.0));
+    // A bare number is per second
+    assert_eq!(parse_rate("20"), Ok(20.0));
+}
+
+#[test]
+fn invalid_rates_are_rejected() {
+    for rate in &["", "0/s", "-5/s", "1.5/s", "ten/s", "50/d", "50/", "/s", "50/s/m"] {
+        assert!(parse_rate(rate).is_err(), "{} should be rejected", rate);
+    }
+}
+
+#[test]
+fn bucket_spreads_requests() {
+    let start = Instant::now();
+    let interval = Duration::from_millis(100);
+    let mut bucket = Bucket::new(interval, start);
+
+    // Slots reserved at the same time are spaced out by the interval
+    assert_eq!(bucket.reserve(start), start);
+    assert_eq!(bucket.reserve(start), start + interval);
+    assert_eq!(bucket.reserve(start), start + interval * 2);
+
+    // Once the bucket has been idle, the next slot is straight away
+    let later = start + Duration::from_secs(5);
+    assert_eq!(bucket.reserve(later), later);
+    assert_eq!(bucket.reserve(later), later + interval);
+}
+
+#[test]
+fn bucket_fills_gaps_before_later_slots() {
+    let now = Instant::now();
+    let interval = Duration::from_millis(100);
+    let mut bucket = Bucket::new(interval, now);
+
+    // A slot reserved ahead of time leaves the time before it free
+    let later = now + Duration::from_secs(1);
+    assert_eq!(bucket.reserve(later), later);
+    assert_eq!(bucket.reserve(now), now);
+    // Slots too close to it are moved after it
+    assert_eq!(bucket.reserve(later - interval / 2), later + interval);
+}
+
+#[test]
+fn bucket_waits_for_its_start() {
+    // Buckets starting in the future, such as after a pause, don't give
+    // out any slots before then
+    let now = Instant::now();
+    let start = now + Duration::from_secs(30);
+    let mut bucket = Bucket::new(Duration::from_secs(1), start);
+    assert_eq!(bucket.reserve(now), start);
+    assert_eq!(bucket.reserve(now), start + Duration::from_secs(1));
+}
+
+#[test]
+fn dates_are_converted_to_days() {
+    assert_eq!(days_from_civil(1970, 1, 1), 0);
+    assert_eq!(days_from_civil(1969, 12, 31), -1);
+    assert_eq!(days_from_civil(2000, 2, 29), 11016);
+    assert_eq!(days_from_civil(2000, 3, 1), 11017);
+    assert_eq!(days_from_civil(2015, 10, 21), 16729);
+    assert_eq!(days_from_civil(2024, 2, 29), 19782);
+    assert_eq!(days_from_civil(2100, 3, 1), 47541);
+    assert_eq!(days_from_civil(1600, 1, 1), -135140);
+}
+
+#[test]
+fn retry_after_seconds_are_parsed() {
+    assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+    assert_eq!(parse_retry_after(" 5 "), Some(Duration::from_secs(5)));
+    assert_eq!(parse_retry_after("0"), Some(Duration::from_secs(0)));
+}
+
+#[test]
+fn retry_after_dates_are_parsed() {
+    // Dates in the past don't pause at all
+    assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+        Some(Duration::from_secs(0)));
+
+    let retry_at = days_from_civil(2100, 3, 1) * 86400 + 12 * 3600 + 30 * 60 + 15;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    let delay = parse_retry_after("Mon, 01 Mar 2100 12:30:15 GMT").unwrap().as_secs() as i64;
+    assert!((delay - (retry_at - now)).abs() <= 1);
+
+    for value in &["soon", "", "Wed, 21 Oct 2015 07:28:00 UTC", "Wed, 21 Foo 2015 07:28:00 GMT",
+                   "Wed, 21 Oct 2015 07:28 GMT", "21 Oct 2015 07:28:00 GMT"] {
+        assert_eq!(parse_retry_after(value), None, "{} should be rejected", value);
+    }
+}
+
+#[test]
+fn errors_start_back_off() {
+    let limiter = RateLimiter::new(None, None, false);
+    for errors in 1..ERRORS_BEFORE_BACKOFF {
+        limiter.record_response(URL, 0, None, Instant::now());
+        assert_eq!(limiter.consecutive_errors(URL), errors);
+        assert_eq!(backoff_interval(&limiter), None);
+    }
+
+    limiter.record_response(URL, 0, None, Instant::now());
+    assert_eq!(backoff_interval(&limiter), Some(INITIAL_BACKOFF));
+    let events = limiter.backoff_events();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].host, "http://example.com");
+    assert_eq!(events[0].reason, format!("{} consecutive errors", ERRORS_BEFORE_BACKOFF));
+    assert_eq!(events[0].pause, INITIAL_BACKOFF);
+
+    // A normal response resets the count
+    end_pause(&limiter);
+    limiter.record_response(URL, 404, None, Instant::now());
+    assert_eq!(limiter.consecutive_errors(URL), 0);
+}
+
+#[test]
+fn back_off_doubles_up_to_the_maximum() {
+    let limiter = RateLimiter::new(None, None, false);
+    let mut expected = INITIAL_BACKOFF;
+    for _ in 0..10 {
+        limiter.record_response(URL, 429, None, Instant::now());
+        assert_eq!(backoff_interval(&limiter), Some(expected));
+        end_pause(&limiter);
+        expected = if expected * 2 > MAX_BACKOFF { MAX_BACKOFF } else { expected * 2 };
+    }
+    assert_eq!(backoff_interval(&limiter), Some(MAX_BACKOFF));
+}
+
+#[test]
+fn back_off_ramps_down_to_full_speed() {
+    let limiter = RateLimiter::new(None, None, false);
+    limiter.record_response(URL, 429, None, Instant::now());
+    end_pause(&limiter);
+
+    // Each normal response shortens the delay by a quarter until it is
+    // short enough to go back to full speed
+    let mut expected = INITIAL_BACKOFF;
+    loop {
+        expected = expected * 3 / 4;
+        limiter.record_response(URL, 200, None, Instant::now());
+        if expected < MIN_BACKOFF {
+            assert_eq!(backoff_interval(&limiter), None);
+            break;
+        }
+        assert_eq!(backoff_interval(&limiter), Some(expected));
+    }
+}
+
+#[test]
+fn retry_after_is_clamped() {
+    let limiter = RateLimiter::new(None, None, false);
+
+    // A 503 is only backed off from if it says when to retry
+    limiter.record_response(URL, 503, None, Instant::now());
+    assert_eq!(backoff_interval(&limiter), None);
+
+    limiter.record_response(URL, 503, Some(Duration::from_secs(3600)), Instant::now());
+    let events = limiter.backoff_events();
+    assert_eq!(events[0].reason, "503 Service Unavailable");
+    assert_eq!(events[0].pause, MAX_RETRY_AFTER);
+
+    // Requests aren't sent again until the pause is over
+    assert!(limiter.reserve(URL) >= Instant::now() + MAX_RETRY_AFTER - Duration::from_secs(1));
+}
+
+#[test]
+fn responses_from_before_a_pause_are_ignored() {
+    let limiter = RateLimiter::new(None, None, false);
+    let sent_at = Instant::now();
+    limiter.record_response(URL, 429, Some(Duration::from_secs(30)), sent_at);
+
+    // Responses arriving during the pause don't count as errors
+    for _ in 0..5 {
+        limiter.record_response(URL, 0, None, sent_at);
+    }
+    assert_eq!(limiter.consecutive_errors(URL), 0);
+
+    // Nor do ones sent before it began which arrive after it ends
+    end_pause(&limiter);
+    limiter.record_response(URL, 0, None, sent_at);
+    limiter.record_response(URL, 429, None, sent_at);
+    assert_eq!(limiter.consecutive_errors(URL), 0);
+    assert_eq!(limiter.backoff_events().len(), 1);
+
+    // But requests sent after it began do
+    limiter.record_response(URL, 0, None, Instant::now());
+    assert_eq!(limiter.consecutive_errors(URL), 1);
+}
+
+#[test]
+fn host_rate_is_applied_to_each_host() {
+    let limiter = RateLimiter::new(None, Some(10.0), false);
+    let first = limiter.reserve(URL);
+    assert!(limiter.reserve(URL) >= first + Duration::from_millis(100));
+    // Other hosts have their own limit
+    assert!(limiter.reserve("http://example.org/") < first + Duration::from_millis(100));
+}
+
+#[test]
+fn global_rate_is_kept_around_paused_hosts() {
+    let interval = Duration::from_millis(100);
+    let limiter = RateLimiter::new(Some(10.0), None, false);
+    limiter.record_response(URL, 429, Some(Duration::from_secs(2)), Instant::now());
+
+    // The paused host's request doesn't use up the free host's slots,
+    // and nothing is sent alongside it when the pause ends
+    let paused = limiter.reserve(URL);
+    let mut slots = vec![paused];
+    for _ in 0..25 {
+        slots.push(limiter.reserve("http://example.org/"));
+    }
+    assert!(slots[1] < paused);
+    slots.sort();
+    for pair in slots.windows(2) {
+        assert!(pair[1] - pair[0] >= interval, "{:?} apart", pair[1] - pair[0]);
+    }
+}
+
+#[test]
+fn latency_percentiles_are_kept() {
+    let mut latencies = Latencies::default();
+    let mut times: Vec<u64> = (0..200).map(|index| (index * 37) % 900).collect();
+    for time in &times {
+        latencies.record(*time);
+    }
+
+    // Times under a second are kept exactly, so the percentiles are the
+    // same as from the sorted list of times
+    times.sort();
+    for percent in &[0, 50, 95, 100] {
+        assert_eq!(latencies.percentile(*percent), times[(times.len() - 1) * percent / 100]);
+    }
+    assert_eq!(latencies.count, 200);
+    assert_eq!(latencies.total_ms, times.iter().sum::<u64>());
+    assert_eq!(latencies.max_ms, times[times.len() - 1]);
+}
+
+#[test]
+fn slow_latencies_are_rounded() {
+    let mut latencies = Latencies::default();
+    for time in &[12345, 12349, 4999, 1000] {
+        latencies.record(*time);
+    }
+    assert_eq!(latencies.percentile(0), 1000);
+    assert_eq!(latencies.percentile(50), 4990);
+    assert_eq!(latencies.percentile(100), 12300);
+    // The totals aren't rounded
+    assert_eq!(latencies.max_ms, 12349);
+    assert_eq!(latencies.total_ms, 12345 + 12349 + 4999 + 1000);
+}
+
+#[test]
+fn latency_memory_is_bounded() {
+    let mut latencies = Latencies::default();
+    for time in 0..1_000_000 {
+        latencies.record(time % 60_000);
+    }
+    assert_eq!(latencies.count, 1_000_000);
+    assert!(latencies.histogram.len() <= 3000, "{} times kept", latencies.histogram.len());
+}
+
+#[test]
+fn latency_is_summarised_for_each_host() {
+    let limiter = RateLimiter::new(None, None, false);
+    for time in &[10, 20, 30, 40] {
+        limiter.record_latency(URL, *time);
+    }
+    limiter.record_latency("http://example.org/", 5);
+
+    let summaries = limiter.latency_summaries();
+    assert_eq!(summaries.len(), 2);
+    assert_eq!(summaries[0].host, "http://example.com");
+    assert_eq!((summaries[0].requests, summaries[0].average_ms, summaries[0].median_ms,
+        summaries[0].p95_ms, summaries[0].max_ms), (4, 25, 20, 30, 40));
+    assert_eq!(summaries[1].host, "http://example.org");
+}
@@ -23,6 +23,8 @@ use percent_encoding::percent_decode;
 extern crate curl;
 use curl::easy::{Easy2, Handler, WriteError};
 use crate::content_parse;
+use crate::request_engine::RequestEngine;
+use serde::{Serialize, Deserialize};
 
 #[cfg(test)]
 mod request_tests;
@@ -30,13 +32,49 @@ mod request_tests;
 pub struct Collector
 {
     pub contents: Vec<u8>,
-    pub content_len: usize
+    pub content_len: usize,
+    pub status_line: String,
+    pub headers: Vec<String>
+}
+
+// The raw headers and body of a response, kept until the response has
+// been checked against the filters and saved
+pub struct RawResponse {
+    pub headers: Vec<String>,
+    pub body: Vec<u8>
 }
 
 impl Collector {
-    fn clear_buffer(&mut self) {
+    pub fn clear_buffer(&mut self) {
         self.contents = Vec::new();
         self.content_len = 0;
+        self.status_line = String::new();
+        self.headers = Vec::new();
+    }
+
+    // Returns the value of the first header with the given name,
+    // header names are compared case insensitively
+    pub fn header_value(&self, name: &str) -> Option<&str> {
+        for header in &self.headers {
+            if let Some(index) = header.find(":") {
+                if header[0..index].trim().eq_ignore_ascii_case(name) {
+                    return Some(header[index+1..].trim());
+                }
+            }
+        }
+        None
+    }
+
+    // Takes the collected status line, headers and contents, leaving
+    // the buffer empty
+    pub fn take_raw(&mut self) -> RawResponse {
+        self.content_len = 0;
+        let mut headers = vec![std::mem::replace(&mut self.status_line, String::new())];
+        headers.append(&mut self.headers);
+        RawResponse {
+            headers,
+            body: std::mem::replace(&mut self.contents, Vec::new())
+        }
     }
 }
 
@@ -47,49 +85,289 @@ impl Handler for Collector {
         self.content_len += data_len;
         Ok(data_len)
     }
+
+    fn header(&mut self, data: &[u8]) -> bool {
+        let line = String::from_utf8_lossy(data).trim_end().to_string();
+
+        // A status line starts a new set of headers, for example
+        // after a 100 Continue response
+        if line.starts_with("HTTP/") {
+            self.status_line = line;
+            self.headers.clear();
+        }
+        else if !line.is_empty() {
+            self.headers.push(line);
+        }
+        true
+    }
 }
 
 // Struct which contains information about a response
 // This is sent back to the main thread
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct RequestResponse {
     pub url: String,
+    // The verb the request was made with, and the verb given in an
+    // X-HTTP-Method-Override header if the request was a tamper check
+    #[serde(default)]
+    pub verb: HttpVerb,
+    #[serde(default)]
+    pub method_override: Option<HttpVerb>,
+    // The word sent in the headers or body of a request to a fixed url,
+    // as the url alone doesn't tell the responses apart
+    #[serde(default)]
+    pub word: Option<String>,
     pub code: u32,
     pub content_len: usize,
+    // The number of words and lines in the body
+    #[serde(default)]
+    pub words: usize,
+    #[serde(default)]
+    pub lines: usize,
     pub is_directory: bool,
     pub is_listable: bool,
     pub redirect_url: String,
+    // Set if redirects are being followed and this was a redirect
+    pub redirect_chain: Option<RedirectChain>,
     pub found_from_listable: bool,
-    pub parent_depth: u32
+    // The url of the file this is a backup of, if it was found by
+    // requesting the backups of that file
+    #[serde(default)]
+    pub derived_from: Option<String>,
+    pub parent_depth: u32,
+    #[serde(default)]
+    pub headers: ResponseHeaders,
+    #[serde(default)]
+    pub timing: RequestTiming,
+    // Query parameters found to change the response, if they were looked for
+    #[serde(default)]
+    pub parameters: Vec<DiscoveredParameter>,
+    // A simhash of the body, used to compare it to not found pages
+    pub fingerprint: u64,
+    // Set if the request failed, even after any retries
+    pub error: Option<String>
 }
 
-// This function takes an instance of "Easy2", a base URL and a suffix
-// It then makes the request, if the response was not a 404
-// then it will return a RequestResponse struct
-pub fn make_request(mut easy: &mut Easy2<Collector>, url: String) -> RequestResponse {
-
-    // Set the url in the Easy2 instance
-    easy.url(&url).unwrap();
-
-    // Perform the request and check if it's empty
-    // If it's empty then return a RequestResponse struct
-    match perform(&mut easy) {
-        Ok(_v) => {}
-        Err(e) => {
-            println!("Curl error after requesting {} : {}", url, e);
-            let req_response = RequestResponse {
-                url: url.clone(),
-                code: 0,
-                content_len: 0,
-                is_directory:false,
-                is_listable: false,
-                redirect_url: String::from(""),
-                found_from_listable: false,
-                parent_depth: 0
+// The response headers which are kept with each response
+// Headers which weren't in the response are left out when serialized
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResponseHeaders {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub set_cookie: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_length: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub www_authenticate: Option<String>
+}
+
+// Replaced with the current word wherever it appears in the body template
+pub const WORD_PLACEHOLDER: &str = "FUZZ";
+
+// The names of the headers which are kept
+pub const KEPT_HEADERS: [&str; 6] = ["Content-Type", "Server", "Location",
+    "Set-Cookie", "Content-Length", "WWW-Authenticate"];
+
+impl ResponseHeaders {
+    // Picks the kept headers out of the raw header lines of a response
+    pub fn from_lines(lines: &[String]) -> ResponseHeaders {
+        let mut headers = ResponseHeaders::default();
+
+        for line in lines {
+            let index = match line.find(":") {
+                Some(index) => index,
+                None => continue
             };
-            return req_response; 
+            let name = line[0..index].trim().to_lowercase();
+            let value = Some(String::from(line[index+1..].trim()));
+
+            match name.as_str() {
+                "content-type" => headers.content_type = value,
+                "server" => headers.server = value,
+                "location" => headers.location = value,
+                "set-cookie" => headers.set_cookie.extend(value),
+                "content-length" => headers.content_length = value,
+                "www-authenticate" => headers.www_authenticate = value,
+                _ => {}
+            }
+        }
+
+        headers
+    }
+
+    // Returns each kept header which was present as a name and value,
+    // in the order of KEPT_HEADERS
+    pub fn pairs(&self) -> Vec<(&'static str, &str)> {
+        let mut pairs = Vec::new();
+        let singles = [(KEPT_HEADERS[0], &self.content_type),
+                       (KEPT_HEADERS[1], &self.server),
+                       (KEPT_HEADERS[2], &self.location)];
+        for (name, value) in singles.iter() {
+            if let Some(value) = value {
+                pairs.push((*name, value.as_str()));
+            }
+        }
+        for value in &self.set_cookie {
+            pairs.push((KEPT_HEADERS[3], value.as_str()));
+        }
+        let singles = [(KEPT_HEADERS[4], &self.content_length),
+                       (KEPT_HEADERS[5], &self.www_authenticate)];
+        for (name, value) in singles.iter() {
+            if let Some(value) = value {
+                pairs.push((*name, value.as_str()));
+            }
+        }
+        pairs
+    }
+
+    // Returns the values of the header with the given name, which is
+    // compared case insensitively
+    pub fn get(&self, name: &str) -> Vec<&str> {
+        self.pairs().into_iter()
+            .filter(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+            .collect()
+    }
+}
+
+// How long the request took, in milliseconds
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RequestTiming {
+    pub total_ms: u64,
+    // The time until the first byte of the response was received
+    pub first_byte_ms: u64,
+    pub connect_ms: u64
+}
+
+// Where a followed redirect ended up
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RedirectChain {
+    // Each url redirected to, in the order they were requested
+    pub urls: Vec<String>,
+    // The code and size of the last response in the chain
+    pub final_code: u32,
+    pub final_size: usize
+}
+
+// A query parameter which changed the response when it was added
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DiscoveredParameter {
+    pub name: String,
+    // What differed from the response without the parameter
+    pub change: ParameterChange,
+    // The code and size of the response with the parameter
+    pub code: u32,
+    pub size: usize
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParameterChange {
+    Code,
+    Size,
+    Body
+}
+
+impl ParameterChange {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParameterChange::Code => "code",
+            ParameterChange::Size => "size",
+            ParameterChange::Body => "body"
         }
     }
+}
+
+// A request for the engine to make
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub url: String,
+    pub verb: HttpVerb,
+    // Sent as the X-HTTP-Method-Override header if set
+    pub method_override: Option<HttpVerb>,
+    // The word from the wordlist the request is for, which replaces the
+    // placeholder in the body template
+    pub word: String
+}
+
+impl Request {
+    pub fn new(url: String, verb: HttpVerb) -> Request {
+        Request::for_word(url, verb, "")
+    }
+
+    pub fn for_word(url: String, verb: HttpVerb, word: &str) -> Request {
+        Request {
+            url,
+            verb,
+            method_override: None,
+            word: String::from(word)
+        }
+    }
+}
+
+// Makes each of the given requests through the engine, as many at once
+// as its concurrency limit allows
+// The responses are returned in the same order as the requests
+pub fn make_requests(engine: &mut RequestEngine, requests: Vec<Request>) -> Vec<RequestResponse> {
+    make_raw_requests(engine, requests).into_iter()
+        .map(|(response, _)| response)
+        .collect()
+}
+
+// The same as make_requests, but also returns the raw headers and body
+// of each response
+pub fn make_raw_requests(engine: &mut RequestEngine, requests: Vec<Request>)
+    -> Vec<(RequestResponse, RawResponse)> {
+    let tokens: Vec<usize> = requests.into_iter()
+        .map(|request| engine.submit_request(request))
+        .collect();
+
+    tokens.into_iter()
+        .map(|token| engine.wait_for_raw(token))
+        .collect()
+}
+
+// Called by the engine once a transfer has finished, takes the easy that
+// performed it, the request and the result of the transfer
+// and generates a RequestResponse struct from them
+pub fn process_response(easy: &mut Easy2<Collector>, request: &Request,
+                        result: Result<(), Error>) -> RequestResponse {
+    let url = request.url.clone();
+
+    // If the request errored then return a RequestResponse struct
+    // with a code of 0 and the error that occurred
+    if let Err(e) = result {
+        println!("Curl error after requesting {} : {}", url, e);
+        let req_response = RequestResponse {
+            url: url.clone(),
+            verb: request.verb,
+            method_override: request.method_override,
+            word: None,
+            code: 0,
+            content_len: 0,
+            words: 0,
+            lines: 0,
+            is_directory:false,
+            is_listable: false,
+            redirect_url: String::from(""),
+            redirect_chain: None,
+            found_from_listable: false,
+            derived_from: None,
+            parent_depth: 0,
+            headers: ResponseHeaders::default(),
+            timing: RequestTiming::default(),
+            parameters: Vec::new(),
+            fingerprint: 0,
+            error: Some(e.to_string())
+        };
+        return req_response; 
+    }
 
     // Get the response code
     let code = easy.response_code().unwrap();
@@ -97,44 +375,73 @@ pub fn make_request(mut easy: &mut Easy2<Collector>, url: String) -> RequestResp
     // Declare the RequestResponse for the current request
     let mut req_response = RequestResponse {
         url: url.clone(),
+        verb: request.verb,
+        method_override: request.method_override,
+        word: None,
         code: code,
         content_len: 0,
+        words: 0,
+        lines: 0,
         is_directory:false,
         is_listable: false,
         redirect_url: String::from(""),
+        redirect_chain: None,
         found_from_listable: false,
-        parent_depth: 0
+        derived_from: None,
+        parent_depth: 0,
+        headers: ResponseHeaders::default(),
+        timing: RequestTiming::default(),
+        parameters: Vec::new(),
+        fingerprint: 0,
+        error: None
     };
 
     // If the response was a redirect, check if it's a directory
     // Also add the redirect url to the struct
     // Generally, directories will redirect requests to them with no trailing /
     // so that they have a trailing /
-    if code == 301 || code == 302 {
+    // A 303 changes the method of the request, so it isn't used for directories
+    if is_redirect(code) {
 
         // Obtain and url decode the redirect destination
-        let redir_dest = easy.redirect_url().unwrap().unwrap();
-        let redir_dest = percent_decode(redir_dest.as_bytes()).decode_utf8().unwrap();
+        if let Ok(Some(redir_dest)) = easy.redirect_url() {
+            let redir_dest = percent_decode(redir_dest.as_bytes()).decode_utf8_lossy();
 
-        // Clone and url decode the url
-        let dir_url = url.clone() + "/";
-        let dir_url = percent_decode(dir_url.as_bytes()).decode_utf8().unwrap();
+            // Clone and url decode the url
+            let dir_url = url.clone() + "/";
+            let dir_url = percent_decode(dir_url.as_bytes()).decode_utf8_lossy();
 
-        if dir_url == redir_dest {
-            req_response.is_directory = true;
-        }
+            if code != 303 && dir_url == redir_dest {
+                req_response.is_directory = true;
+            }
 
-        req_response.redirect_url = redir_dest.to_string();
+            req_response.redirect_url = redir_dest.to_string();
+        }
     }
 
     // Get the contents of the response and set the length in the struct
     let contents = easy.get_ref();
+    req_response.headers = ResponseHeaders::from_lines(&contents.headers);
+    req_response.timing = RequestTiming {
+        total_ms: easy.total_time().map(duration_ms).unwrap_or(0),
+        first_byte_ms: easy.starttransfer_time().map(duration_ms).unwrap_or(0),
+        connect_ms: easy.connect_time().map(duration_ms).unwrap_or(0)
+    };
     req_response.content_len = contents.content_len;
+    let body = String::from_utf8_lossy(&contents.contents);
+    req_response.words = body.split_whitespace().count();
+    req_response.lines = body.lines().count();
+    // Pages may echo the word instead of the path when it was sent in
+    // the headers or body of a request to a fixed url
+    let echoed = if request.word.is_empty() || url.contains(&request.word) {
+        &url
+    } else { &request.word };
+    req_response.fingerprint = content_parse::simhash(&body, echoed);
 
     req_response
 }
 
-pub fn listable_check(easy: &mut Easy2<Collector>, original_url: String, 
+pub fn listable_check(engine: &mut RequestEngine, original_url: String, 
                     max_recursion_depth: Option<i32>, parent_depth: i32,
                     scrape_listable: bool) -> Vec<RequestResponse> {
 
@@ -143,8 +450,9 @@ pub fn listable_check(easy: &mut Easy2<Collector>, original_url: String,
     if !dir_url.ends_with("/") {
         dir_url = dir_url + "/";
     }
-    let mut response = make_request(easy, dir_url.clone());
-    let content = get_content(easy).to_lowercase();
+    let token = engine.submit(dir_url.clone());
+    let (mut response, content) = engine.wait_for_content(token);
+    let content = content.to_lowercase();
     let mut output_list:Vec<RequestResponse> = Vec::new();
 
     match response.code {
@@ -167,9 +475,11 @@ pub fn listable_check(easy: &mut Easy2<Collector>, original_url: String,
         }
         // If the code returned was not a 200 then create a struct
         // indicating that this is a folder, then return it
+        // The directory is still known to exist if the request errored
         _ => {
             response.is_directory = true;
             response.is_listable = false;
+            response.error = None;
             output_list.push(response);
             return output_list
         }
@@ -206,13 +516,13 @@ pub fn listable_check(easy: &mut Easy2<Collector>, original_url: String,
                     output_list.push(fabricate_request_response(scraped_url, true, false));
                 }
                 else {
-                    output_list.append(&mut listable_check(easy, scraped_url, max_recursion_depth, parent_depth, scrape_listable));
+                    output_list.append(&mut listable_check(engine, scraped_url, max_recursion_depth, parent_depth, scrape_listable));
                 }
             }
             // If there is no limit to recursion depth
             // then call this function on the discovered folder
             else {
-                output_list.append(&mut listable_check(easy, scraped_url, max_recursion_depth, parent_depth, scrape_listable));
+                output_list.append(&mut listable_check(engine, scraped_url, max_recursion_depth, parent_depth, scrape_listable));
             }
         }
     }
@@ -223,14 +533,10 @@ pub fn listable_check(easy: &mut Easy2<Collector>, original_url: String,
 // Creates an easy2 instance based on the parameters provided by the user
 pub fn generate_easy(global_opts: &Arc<GlobalOpts>) -> Easy2<Collector>
 {
-    // Create a new curl Easy2 instance and set it to use GET requests
-    let mut easy = Easy2::new(Collector{contents: Vec::new(), content_len: 0});
-
-    match &global_opts.http_verb {
-        HttpVerb::Get => { easy.get(true).unwrap(); },
-        HttpVerb::Head => { easy.nobody(true).unwrap(); },
-        HttpVerb::Post => { easy.post(true).unwrap(); }
-    }
+    // Create a new curl Easy2 instance, the verb and headers are set
+    // for each request by prepare_request
+    let mut easy = Easy2::new(Collector{contents: Vec::new(), content_len: 0,
+        status_line: String::new(), headers: Vec::new()});
 
     // Set the timeout of the easy
     easy.timeout(Duration::from_secs(global_opts.timeout as u64)).unwrap();
@@ -262,31 +568,96 @@ pub fn generate_easy(global_opts: &Arc<GlobalOpts>) -> Easy2<Collector>
         easy.cookie(cookies).unwrap();
     }
 
-    // Set headers
-    if let Some(headers) =  &global_opts.headers {
-        let mut header_list = curl::easy::List::new();
+    easy
+}
+
+// Sets the url, verb and headers of a pooled easy for the given request,
+// replacing any left over from the request it was last used for
+pub fn prepare_request(easy: &mut Easy2<Collector>, global_opts: &GlobalOpts,
+                       request: &Request) {
+    easy.url(&request.url).unwrap();
+
+    // Both of these also switch off the body being skipped for HEAD
+    // The body is always set for verbs which send one, as a handle which
+    // sent a body before would otherwise send it again
+    let sends_body = request.verb.has_body();
+    if sends_body {
+        let body = match &global_opts.data {
+            Some(data) => fill_template(data, &request.word),
+            None => String::new()
+        };
+        easy.post(true).unwrap();
+        easy.post_fields_copy(body.as_bytes()).unwrap();
+    }
+    else {
+        easy.get(true).unwrap();
+    }
+    easy.nobody(request.verb == HttpVerb::Head).unwrap();
+    easy.custom_request(request.verb.method()).unwrap();
+
+    let mut header_list = curl::easy::List::new();
+    if let Some(headers) = &global_opts.headers {
         for header in headers {
-            header_list.append(header).unwrap();
+            header_list.append(&fill_template(header, &request.word)).unwrap();
+        }
+    }
+    if sends_body {
+        if let Some(content_type) = &global_opts.content_type {
+            header_list.append(&format!("Content-Type: {}", content_type)).unwrap();
+        }
+    }
+    // Virtual hosts are scanned by sending each word in the Host header,
+    // requests which aren't for a word go to the default host
+    if let Some(vhost) = &global_opts.vhost {
+        if !request.word.is_empty() {
+            header_list.append(&format!("Host: {}",
+                vhost.host(&request.url, &request.word))).unwrap();
         }
-        easy.http_headers(header_list).unwrap();
     }
+    if let Some(method_override) = request.method_override {
+        header_list.append(&format!("X-HTTP-Method-Override: {}",
+            method_override.method())).unwrap();
+    }
+    easy.http_headers(header_list).unwrap();
+}
 
-    easy
+// Replaces each placeholder in a body or header with the word being requested
+pub fn fill_template(template: &str, word: &str) -> String {
+    template.replace(WORD_PLACEHOLDER, word)
 }
 
-// Before each request, the buffer should be cleared
-// This provides support for chunked http responses
-fn perform(easy: &mut Easy2<Collector>) -> Result<(), Error>
-{
-    easy.get_mut().clear_buffer();
-    easy.perform()
+// Returns true if the error is likely to be temporary,
+// such as a timeout or the connection being reset
+pub fn is_transient(error: &Error) -> bool {
+    error.is_operation_timedout() || error.is_couldnt_connect()
+        || error.is_send_error() || error.is_recv_error()
+        || error.is_got_nothing()
 }
 
-// Get the current content of the given easy and return it as a string
-fn get_content(easy: &mut Easy2<Collector>) -> String
-{
-    let contents = easy.get_ref();
-    String::from_utf8_lossy(&contents.contents).to_string()
+#[inline]
+fn duration_ms(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + duration.subsec_millis() as u64
+}
+
+// Returns true if the code is one which redirects to another url
+pub fn is_redirect(code: u32) -> bool {
+    match code {
+        301 | 302 | 303 | 307 | 308 => true,
+        _ => false
+    }
+}
+
+// Returns the scheme, host and port section of the given url
+pub fn url_host(url: &str) -> String {
+    let start = match url.find("://") {
+        Some(index) => index + 3,
+        None => 0
+    };
+
+    match url[start..].find("/") {
+        Some(end) => String::from(&url[0..start+end]),
+        None => String::from(url)
+    }
 }
 
 // Generate a struct for a response for use when a request hasn't been made
@@ -296,12 +667,24 @@ pub fn fabricate_request_response(url: String, is_directory: bool, is_listable:
 
     RequestResponse {
         url: url.clone(),
+        verb: HttpVerb::Get,
+        method_override: None,
+        word: None,
         code: 0,
         content_len: 0,
+        words: 0,
+        lines: 0,
         is_directory: is_directory,
         is_listable: is_listable,
         redirect_url: String::from(""),
+        redirect_chain: None,
         found_from_listable: true,
-        parent_depth: 0
+        derived_from: None,
+        parent_depth: 0,
+        headers: ResponseHeaders::default(),
+        timing: RequestTiming::default(),
+        parameters: Vec::new(),
+        fingerprint: 0,
+        error: None
     }
 }
\ No newline at end of file
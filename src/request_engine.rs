@@ -0,0 +1,380 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+use curl::easy::Easy2;
+use curl::multi::{Easy2Handle, Multi};
+use crate::arg_parse::{GlobalOpts, HttpVerb};
+use crate::rate_limit::{self, RateLimiter};
+use crate::request::{self, Collector, RawResponse, RedirectChain, Request, RequestResponse,
+    WORD_PLACEHOLDER};
+use percent_encoding::{utf8_percent_encode, QUERY_ENCODE_SET};
+
+// The delay before the first retry of a failed request,
+// this doubles with each subsequent retry
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+// Drives many concurrent transfers from a single thread using curl's
+// multi interface. Easy handles are kept in a pool and reused between
+// requests so that connections to the target can be kept alive
+pub struct RequestEngine {
+    // Declared before the multi handle so that any transfers still in
+    // flight are dropped before the multi handle is cleaned up
+    // Stores the request, attempt number and when it was sent
+    in_flight: HashMap<usize, (Request, u32, Instant, Easy2Handle<Collector>)>,
+    multi: Multi,
+    global_opts: Arc<GlobalOpts>,
+    rate_limiter: Arc<RateLimiter>,
+    idle_handles: Vec<Easy2<Collector>>,
+    completed: VecDeque<(usize, Request, RequestResponse, RawResponse)>,
+    // Requests which failed and are waiting to be retried, stored as
+    // the time to retry, the token, the request and the attempt number
+    retry_queue: Vec<(Instant, usize, Request, u32)>,
+    // Requests waiting for the slot the rate limiter gave them, stored in
+    // the same way as retries. The other transfers keep being driven
+    // while these wait, rather than blocking the thread
+    scheduled: Vec<(Instant, usize, Request, u32)>,
+    // Requests submitted while the engine was full, which are started
+    // in order as other requests finish, stored with their token and
+    // attempt number
+    queued: VecDeque<(usize, Request, u32)>,
+    // Redirects being followed, keyed by the token of the latest request
+    // in the chain, storing the token, request, response and raw response
+    // of the original
+    following: HashMap<usize, (usize, Request, RequestResponse, RawResponse)>,
+    next_token: usize,
+    max_in_flight: usize,
+    // Set when every request in a folder is to the same url, either from
+    // a request file or when scanning for virtual hosts
+    fixed_url: bool
+}
+
+impl RequestEngine {
+    pub fn new(global_opts: &Arc<GlobalOpts>, max_in_flight: u32,
+               rate_limiter: Arc<RateLimiter>) -> RequestEngine {
+        RequestEngine {
+            in_flight: HashMap::new(),
+            multi: Multi::new(),
+            global_opts: global_opts.clone(),
+            rate_limiter,
+            idle_handles: Vec::new(),
+            completed: VecDeque::new(),
+            retry_queue: Vec::new(),
+            scheduled: Vec::new(),
+            queued: VecDeque::new(),
+            following: HashMap::new(),
+            next_token: 0,
+            max_in_flight: max_in_flight as usize,
+            fixed_url: global_opts.vhost.is_some() || global_opts.url_template.as_ref()
+                .map_or(false, |template| !template.contains(WORD_PLACEHOLDER))
+        }
+    }
+
+    // Returns true if another request can be submitted without having
+    // to wait for one of the requests in flight to finish
+    pub fn has_capacity(&self) -> bool {
+        self.queued.is_empty() && self.has_free_slot()
+    }
+
+    // Returns true if another request can be started without going over
+    // the concurrency limit of the engine
+    #[inline]
+    fn has_free_slot(&self) -> bool {
+        self.in_flight.len() + self.scheduled.len() < self.max_in_flight
+    }
+
+    // The number of submitted requests whose responses haven't been
+    // returned yet
+    pub fn pending(&self) -> usize {
+        self.in_flight.len() + self.retry_queue.len() + self.scheduled.len() +
+            self.queued.len() + self.completed.len()
+    }
+
+    // Starts a request for the given url with the first verb and returns
+    // the token which identifies its response. If the engine is full then
+    // the request is started once another finishes, and if the rate limit
+    // doesn't allow it to be made yet then it is started once it does
+    pub fn submit(&mut self, url: String) -> usize {
+        let verb = self.global_opts.http_verbs[0];
+        self.submit_request(Request::new(url, verb))
+    }
+
+    // The same as submit, but for any request
+    pub fn submit_request(&mut self, request: Request) -> usize {
+        let token = self.next_token;
+        self.next_token += 1;
+
+        if self.has_capacity() {
+            self.start(token, request, 0);
+        }
+        else {
+            self.queued.push_back((token, request, 0));
+        }
+
+        token
+    }
+
+    fn start(&mut self, token: usize, request: Request, attempt: u32) {
+        let slot = self.rate_limiter.reserve(&request.url);
+        if slot > Instant::now() {
+            self.scheduled.push((slot, token, request, attempt));
+            return;
+        }
+        self.launch(token, request, attempt);
+    }
+
+    // Adds the request to the multi handle, its rate limit slot must
+    // already have been reserved
+    fn launch(&mut self, token: usize, request: Request, attempt: u32) {
+        let global_opts = &self.global_opts;
+        let mut easy = self.idle_handles.pop()
+            .unwrap_or_else(|| request::generate_easy(global_opts));
+
+        // Before each request, the buffer should be cleared
+        // This provides support for chunked http responses
+        easy.get_mut().clear_buffer();
+        request::prepare_request(&mut easy, global_opts, &request);
+
+        let mut handle = self.multi.add2(easy).unwrap();
+        handle.set_token(token).unwrap();
+        self.in_flight.insert(token, (request, attempt, Instant::now(), handle));
+    }
+
+    // Blocks until the request with the given token has completed and
+    // returns its response. Other responses which complete in the
+    // meantime are kept to be returned by next_response
+    pub fn wait_for(&mut self, token: usize) -> RequestResponse {
+        self.wait_for_raw(token).0
+    }
+
+    // The same as wait_for, but also returns the body of the response
+    pub fn wait_for_content(&mut self, token: usize) -> (RequestResponse, String) {
+        let (response, raw) = self.wait_for_raw(token);
+        (response, String::from_utf8_lossy(&raw.body).to_string())
+    }
+
+    // The same as wait_for, but also returns the raw headers and body
+    pub fn wait_for_raw(&mut self, token: usize) -> (RequestResponse, RawResponse) {
+        loop {
+            if let Some(position) = self.completed.iter()
+                    .position(|(completed_token, _, _, _)| *completed_token == token) {
+                let (_, _, response, raw) = self.completed.remove(position).unwrap();
+                return (response, raw);
+            }
+            self.drive();
+        }
+    }
+
+    // Returns the next response to complete with the request it was for
+    // and its raw headers and body, blocking until one does. Returns None
+    // if there are no requests left in flight
+    pub fn next_response_with_content(&mut self)
+        -> Option<(Request, RequestResponse, RawResponse)> {
+        loop {
+            if let Some((_, request, response, raw)) = self.completed.pop_front() {
+                return Some((request, response, raw));
+            }
+            if self.in_flight.is_empty() && self.retry_queue.is_empty() &&
+                    self.scheduled.is_empty() && self.queued.is_empty() {
+                return None;
+            }
+            self.drive();
+        }
+    }
+
+    // Performs any pending work on the in flight transfers and moves
+    // the finished ones to the completed queue. If none finished then
+    // wait for activity on the sockets before returning
+    fn drive(&mut self) {
+        self.start_due_requests();
+        self.start_queued();
+
+        // If only waiting requests are left then wait until the next one
+        // is due
+        if self.in_flight.is_empty() {
+            if let Some(delay) = self.time_to_next_start() {
+                thread::sleep(delay);
+            }
+            return;
+        }
+
+        self.multi.perform().unwrap();
+
+        let mut finished = Vec::new();
+        let in_flight = &self.in_flight;
+        self.multi.messages(|message| {
+            if let Ok(token) = message.token() {
+                if let Some((_, _, _, handle)) = in_flight.get(&token) {
+                    if let Some(result) = message.result_for2(handle) {
+                        finished.push((token, result));
+                    }
+                }
+            }
+        });
+
+        if finished.is_empty() {
+            let mut timeout = Duration::from_millis(100);
+            if let Some(delay) = self.time_to_next_start() {
+                if delay < timeout { timeout = delay; }
+            }
+            self.multi.wait(&mut [], timeout).unwrap();
+            return;
+        }
+
+        for (token, result) in finished {
+            let (request, attempt, sent_at, handle) = self.in_flight.remove(&token).unwrap();
+            let mut easy = self.multi.remove2(handle).unwrap();
+
+            // Retry temporary failures until the retries run out,
+            // waiting twice as long before each retry
+            if let Err(error) = &result {
+                if attempt < self.global_opts.retries && request::is_transient(error) {
+                    if self.global_opts.verbose {
+                        println!("Retrying {} after error: {}", request.url, error);
+                    }
+                    self.rate_limiter.record_response(&request.url, 0, None, sent_at);
+                    self.idle_handles.push(easy);
+
+                    let retry_at = Instant::now() + RETRY_DELAY * 2u32.pow(attempt);
+                    self.retry_queue.push((retry_at, token, request, attempt + 1));
+                    continue;
+                }
+            }
+
+            let mut response = request::process_response(&mut easy, &request, result);
+            if self.fixed_url && !request.word.is_empty() {
+                response.word = Some(request.word.clone());
+            }
+
+            let retry_after = easy.get_ref().header_value("Retry-After")
+                .and_then(rate_limit::parse_retry_after);
+            self.rate_limiter.record_response(&response.url, response.code, retry_after,
+                sent_at);
+            if response.error.is_none() {
+                self.rate_limiter.record_latency(&response.url, response.timing.total_ms);
+            }
+
+            // Keep the headers and body around until the response has been
+            // collected, as the handle may be reused before then
+            let raw = easy.get_mut().take_raw();
+            self.idle_handles.push(easy);
+            self.complete(token, request, response, raw);
+        }
+    }
+
+    // Handles a finished request, which is either returned or if it was
+    // a redirect to be followed then the next request in the chain is made
+    fn complete(&mut self, token: usize, request: Request, response: RequestResponse,
+                raw: RawResponse) {
+        let (original_token, original_request, mut original, original_raw) =
+            match self.following.remove(&token) {
+            Some((original_token, original_request, mut original, original_raw)) => {
+                if let Some(chain) = &mut original.redirect_chain {
+                    chain.final_code = response.code;
+                    chain.final_size = response.content_len;
+                }
+                (original_token, original_request, original, original_raw)
+            },
+            None => (token, request.clone(), response.clone(), raw)
+        };
+
+        let chain_len = original.redirect_chain.as_ref()
+            .map(|chain| chain.urls.len())
+            .unwrap_or(0) as u32;
+
+        // Directories are requested again with a trailing slash anyway,
+        // and redirects to other hosts are out of scope
+        let follow = chain_len < self.global_opts.follow_redirects
+            && request::is_redirect(response.code)
+            && !original.is_directory
+            && !response.redirect_url.is_empty()
+            && request::url_host(&response.redirect_url) == request::url_host(&original.url);
+
+        if !follow {
+            self.completed.push_back((original_token, original_request, original, original_raw));
+            return;
+        }
+
+        let chain = original.redirect_chain.get_or_insert(RedirectChain {
+            urls: Vec::new(),
+            final_code: response.code,
+            final_size: response.content_len
+        });
+        chain.urls.push(response.redirect_url.clone());
+
+        // A 303 is always followed with a GET, other redirects keep the verb
+        let url = utf8_percent_encode(&response.redirect_url, QUERY_ENCODE_SET).to_string();
+        let verb = if response.code == 303 { HttpVerb::Get } else { request.verb };
+        let next_request = Request {
+            url,
+            verb,
+            method_override: request.method_override,
+            word: request.word
+        };
+        let next_token = self.next_token;
+        self.next_token += 1;
+        self.following.insert(next_token,
+            (original_token, original_request, original, original_raw));
+        self.start(next_token, next_request, 0);
+    }
+
+    // Queues any failed requests which are due to be retried ahead of
+    // the other queued requests, and launches any requests whose rate
+    // limit slot has arrived
+    fn start_due_requests(&mut self) {
+        let now = Instant::now();
+        let (due, waiting): (Vec<_>, Vec<_>) = self.retry_queue.drain(..)
+            .partition(|(retry_at, _, _, _)| *retry_at <= now);
+        self.retry_queue = waiting;
+
+        for (_, token, request, attempt) in due.into_iter().rev() {
+            self.queued.push_front((token, request, attempt));
+        }
+
+        let (due, waiting) = self.scheduled.drain(..)
+            .partition(|(slot, _, _, _)| *slot <= now);
+        self.scheduled = waiting;
+
+        for (_, token, request, attempt) in due {
+            self.launch(token, request, attempt);
+        }
+    }
+
+    // Starts queued requests until the engine is full
+    fn start_queued(&mut self) {
+        while self.has_free_slot() {
+            match self.queued.pop_front() {
+                Some((token, request, attempt)) => self.start(token, request, attempt),
+                None => break
+            }
+        }
+    }
+
+    // How long until the next retry or rate limited request is due
+    fn time_to_next_start(&self) -> Option<Duration> {
+        let now = Instant::now();
+        self.retry_queue.iter().chain(self.scheduled.iter())
+            .map(|(start_at, _, _, _)| *start_at)
+            .min()
+            .map(|start_at| if start_at > now { start_at - now } else { Duration::from_secs(0) })
+    }
+}
@@ -0,0 +1,156 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{fs, process::exit};
+use crate::arg_parse::HttpVerb;
+use crate::request::WORD_PLACEHOLDER;
+
+#[cfg(test)]
+mod tests;
+
+// Headers which curl sets itself for each request, so are left out of
+// the template. Compressed bodies would also throw off the sizes and
+// word counts of responses
+const DROPPED_HEADERS: [&str; 3] = ["Content-Length", "Connection", "Accept-Encoding"];
+
+// A raw HTTP request, such as one saved from Burp, which the scan is
+// driven from. The placeholder may appear in the url, headers and body
+#[derive(Debug, PartialEq)]
+pub struct RequestTemplate {
+    pub verb: HttpVerb,
+    pub url: String,
+    pub headers: Vec<String>,
+    pub body: Option<String>
+}
+
+impl RequestTemplate {
+    // Reads a template from a file, exiting if it can't be parsed
+    pub fn load(filename: &str, scheme: &str) -> RequestTemplate {
+        let contents = fs::read_to_string(filename)
+            .unwrap_or_else(|error| {
+                println!("Reading request file \"{}\" failed: {}", filename, error);
+                exit(2);
+            });
+
+        RequestTemplate::parse(&contents, scheme)
+            .unwrap_or_else(|error| {
+                println!("Parsing request file \"{}\" failed: {}", filename, error);
+                exit(2);
+            })
+    }
+
+    // Parses a raw request. The request line may give either a path,
+    // which is requested from the Host header using the given scheme,
+    // or a full url as sent to a proxy
+    pub fn parse(contents: &str, scheme: &str) -> Result<RequestTemplate, String> {
+        let (head, body) = match contents.find("\r\n\r\n") {
+            Some(index) => (&contents[0..index], &contents[index+4..]),
+            None => match contents.find("\n\n") {
+                Some(index) => (&contents[0..index], &contents[index+2..]),
+                None => (contents, "")
+            }
+        };
+
+        let mut lines = head.lines();
+        let request_line = lines.next().unwrap_or("");
+        let parts: Vec<&str> = request_line.split_whitespace().collect();
+        if parts.len() != 3 || !parts[2].starts_with("HTTP/") {
+            return Err(format!("\"{}\" isn't a request line", request_line));
+        }
+
+        let verb = parts[0].parse::<HttpVerb>()
+            .map_err(|_| format!("the {} method isn't supported", parts[0]))?;
+
+        let mut host = None;
+        let mut headers = Vec::new();
+        for line in lines {
+            let (name, value) = match line.find(':') {
+                Some(index) => (line[0..index].trim(), line[index+1..].trim()),
+                None => return Err(format!("\"{}\" isn't a header", line))
+            };
+
+            if name.eq_ignore_ascii_case("Host") {
+                host = Some(value);
+            }
+            if !DROPPED_HEADERS.iter().any(|dropped| name.eq_ignore_ascii_case(dropped)) {
+                headers.push(format!("{}: {}", name, value));
+            }
+        }
+
+        let target = parts[1];
+        let mut url = if target.starts_with("http://") || target.starts_with("https://") {
+            String::from(target)
+        }
+        else if target.starts_with('/') {
+            match host {
+                Some(host) => format!("{}://{}{}", scheme, host, target),
+                None => return Err(String::from("there is no Host header"))
+            }
+        }
+        else {
+            return Err(format!("\"{}\" isn't a path or url", target));
+        };
+
+        // A url without a path, which may still have a query, is given
+        // the root path
+        let host_start = url.find("://").unwrap() + 3;
+        let path_start = match url[host_start..].find(|c| c == '/' || c == '?') {
+            Some(index) => host_start + index,
+            None => url.len()
+        };
+        if !url[path_start..].starts_with('/') {
+            url.insert(path_start, '/');
+        }
+
+        // The placeholder can only be used in the path, as the host
+        // has to be connected to
+        if url[0..path_start].contains(WORD_PLACEHOLDER) {
+            return Err(format!("{} can't be used in the host", WORD_PLACEHOLDER));
+        }
+
+        // Editors usually end the file with a newline which isn't part
+        // of the body
+        let body = body.trim_end_matches(|c| c == '\r' || c == '\n');
+        let body = if body.is_empty() { None } else { Some(String::from(body)) };
+
+        Ok(RequestTemplate {
+            verb,
+            url,
+            headers,
+            body
+        })
+    }
+
+    // The folder which is scanned, which is the url up to the last slash
+    // before the placeholder, or before any query if there isn't one
+    pub fn folder(&self) -> String {
+        let end = self.url.find(WORD_PLACEHOLDER)
+            .or_else(|| self.url.find('?'))
+            .unwrap_or(self.url.len());
+        let end = self.url[0..end].rfind('/').unwrap();
+
+        String::from(&self.url[0..end])
+    }
+
+    // Returns true if the word is put straight after the folder, so the
+    // template can be scanned the same way as any other folder including
+    // recursing into directories found
+    pub fn is_plain_folder(&self) -> bool {
+        self.url.ends_with(&format!("/{}", WORD_PLACEHOLDER)) &&
+            self.url.matches(WORD_PLACEHOLDER).count() == 1
+    }
+}
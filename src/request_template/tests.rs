@@ -0,0 +1,83 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::RequestTemplate;
+use crate::arg_parse::HttpVerb;
+
+#[test]
+fn templates_are_parsed() {
+    let template = RequestTemplate::parse(
+        "POST /api/FUZZ?debug=1 HTTP/1.1\r\n\
+         Host: example.com:8080\r\n\
+         Cookie: session=abc\r\n\
+         Content-Length: 16\r\n\
+         Accept-Encoding: gzip, deflate\r\n\
+         \r\n\
+         {\"name\":\"FUZZ\"}\r\n", "https").unwrap();
+
+    assert_eq!(template, RequestTemplate {
+        verb: HttpVerb::Post,
+        url: String::from("https://example.com:8080/api/FUZZ?debug=1"),
+        headers: vec![String::from("Host: example.com:8080"),
+            String::from("Cookie: session=abc")],
+        body: Some(String::from("{\"name\":\"FUZZ\"}"))
+    });
+    assert_eq!(template.folder(), "https://example.com:8080/api");
+    assert!(!template.is_plain_folder());
+
+    // Full urls, as sent to a proxy, are used as they are
+    let template = RequestTemplate::parse(
+        "get http://example.com/admin/FUZZ HTTP/1.1\nHost: example.com\n", "https").unwrap();
+    assert_eq!(template.verb, HttpVerb::Get);
+    assert_eq!(template.url, "http://example.com/admin/FUZZ");
+    assert_eq!(template.body, None);
+    assert_eq!(template.folder(), "http://example.com/admin");
+    assert!(template.is_plain_folder());
+
+    // Full urls without a path are given the root path, before any query
+    let template = RequestTemplate::parse(
+        "GET http://example.com?q=FUZZ HTTP/1.1\n", "https").unwrap();
+    assert_eq!(template.url, "http://example.com/?q=FUZZ");
+    assert_eq!(template.folder(), "http://example.com");
+    let template = RequestTemplate::parse(
+        "GET http://example.com HTTP/1.1\n", "https").unwrap();
+    assert_eq!(template.url, "http://example.com/");
+
+    // Without a placeholder in the url the folder is the one requested
+    let template = RequestTemplate::parse(
+        "PUT /users/1?x=y HTTP/1.1\nHost: example.com\n\nname=FUZZ", "http").unwrap();
+    assert_eq!(template.folder(), "http://example.com/users");
+    assert_eq!(template.body, Some(String::from("name=FUZZ")));
+}
+
+#[test]
+fn invalid_templates_are_rejected() {
+    let invalid = [
+        "",
+        "GET /\n",
+        "BREW /pot HTTP/1.1\nHost: example.com\n",
+        "GET / HTTP/1.1\nUser-Agent: test\n",
+        "GET / HTTP/1.1\nHost: FUZZ.example.com\n",
+        "GET http://FUZZ.example.com/ HTTP/1.1\n",
+        "GET / HTTP/1.1\nHost: example.com\nnot a header\n",
+    ];
+
+    for contents in invalid.iter() {
+        assert!(RequestTemplate::parse(contents, "https").is_err(),
+            "{:?} should be rejected", contents);
+    }
+}
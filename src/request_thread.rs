@@ -16,19 +16,30 @@
 // along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::{
-    sync::{Arc, mpsc},
+    sync::{Arc, mpsc, atomic::{AtomicBool, Ordering}},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 extern crate curl;
-use crate::arg_parse;
+use crate::arg_parse::{self, HttpVerb};
+use crate::backup;
+use crate::output::ScanEvent;
+use crate::param_discovery;
+use crate::rate_limit::RateLimiter;
 use crate::request;
+use crate::request_engine::RequestEngine;
+use crate::response_saver::ResponseSaver;
+use crate::state::Checkpoint;
 use crate::wordlist;
 use crate::validator_thread;
 
 pub fn thread_spawn(dir_tx: mpsc::Sender<request::RequestResponse>, 
     output_tx: mpsc::Sender<request::RequestResponse>,
-    uri_gen: wordlist::UriGenerator, global_opts: Arc<arg_parse::GlobalOpts>) {
+    event_tx: mpsc::Sender<ScanEvent>,
+    backup_tx: mpsc::Sender<wordlist::UriGenerator>,
+    mut uri_gen: wordlist::UriGenerator, global_opts: Arc<arg_parse::GlobalOpts>,
+    rate_limiter: Arc<RateLimiter>, checkpoint: Option<Arc<Checkpoint>>,
+    response_saver: Option<Arc<ResponseSaver>>, stopping: Arc<AtomicBool>) {
 
     let hostname = uri_gen.hostname.clone();
 
@@ -36,56 +47,147 @@ pub fn thread_spawn(dir_tx: mpsc::Sender<request::RequestResponse>,
         println!("Scanning {}", hostname);
     }
 
-    let mut easy = request::generate_easy(&global_opts);
+    let mut engine = RequestEngine::new(&global_opts, global_opts.concurrency,
+        rate_limiter.clone());
 
-    let mut consecutive_errors = 0;
     let parent_depth = uri_gen.parent_depth;
 
-    let validator = uri_gen.validator.clone();
+    let mut validator = uri_gen.validator.clone();
+    let mut last_validated = Instant::now();
 
-    // For each item in the wordlist, call the request function on it
-    // Then if there is a response send it to main
-    for uri in uri_gen {
-        let mut response = request::make_request(&mut easy, uri.clone());
+    // Keep the engine filled with requests for items in the wordlist
+    // Then as each response arrives send it to main
+    // If the scan is stopping then just wait for the requests in flight
+    loop {
+        // Check the not found response of the directory hasn't changed
+        // since it was last validated, and use the new one if it has
+        if global_opts.revalidate != 0 && validator.is_some() &&
+                last_validated.elapsed() >= Duration::from_secs(global_opts.revalidate as u64) {
+            last_validated = Instant::now();
+            if let Some(new_validator) = revalidate(&mut engine, &uri_gen, &global_opts,
+                    validator.as_ref().unwrap(), &event_tx) {
+                validator = Some(new_validator.clone());
+                uri_gen.validator = Some(new_validator);
+            }
+        }
 
-        let code = response.code.clone();
+        while engine.has_capacity() && !stopping.load(Ordering::SeqCst) {
+            match uri_gen.next() {
+                Some((uri, word)) => {
+                    engine.submit_request(request::Request::for_word(uri, uri_gen.verb, &word));
+                },
+                None => break
+            }
+        }
+
+        let (request, mut response, raw) = match engine.next_response_with_content() {
+            Some(response) => response,
+            None => break
+        };
+
+        // Directories are only detected with the first verb,
+        // so that each one is only scanned once
+        if uri_gen.verb != global_opts.http_verbs[0] {
+            response.is_directory = false;
+        }
+
+        // Backups are reported as derived from the file they were made from
+        if let Some(derived_from) = &uri_gen.derived_from {
+            response.is_directory = false;
+            response.derived_from = Some(derived_from.clone());
+        }
+
+        let failed = response.error.is_some();
+        let word = response.word.clone();
+        let refused = response.code == 401 || response.code == 403 || response.code == 405;
+        let tamper_response = if global_opts.tamper && !failed && !response.is_directory && refused {
+            Some(response.clone())
+        } else { None };
 
         // If the url is a directory, then check if it's listable
         // This may also scrape listable directories if the parameter is set
         // Then return each discovered item to the main thread
-        if response.is_directory {
-            let mut response_list = request::listable_check(&mut easy, response.url, 
+        let reported = if response.is_directory {
+            let mut response_list = request::listable_check(&mut engine, response.url, 
                 global_opts.max_recursion_depth, response.parent_depth as i32, global_opts.scrape_listable);
 
             let mut original_response = response_list.remove(0);
             original_response.found_from_listable = false;
             original_response.parent_depth = parent_depth;
-            send_response(&dir_tx, &output_tx, &global_opts, original_response, &validator);
+            let reported = send_response(&dir_tx, &output_tx, &global_opts,
+                original_response, &raw.body, &validator, &checkpoint);
 
+            // Scraped paths weren't requested, so have no body of their own
             for mut scraped_response in response_list {
                 scraped_response.parent_depth = parent_depth;
-                send_response(&dir_tx, &output_tx, &global_opts, scraped_response, &validator);
+                send_response(&dir_tx, &output_tx, &global_opts, scraped_response, &[],
+                    &validator, &checkpoint);
             }
-
+            reported
         } 
         // If it isn't a directory then just send the response to the main thread
         else {
             response.parent_depth = parent_depth;
-            send_response(&dir_tx, &output_tx, &global_opts, response, &validator); 
+
+            // Look for the parameters of files which are going to be reported
+            if !global_opts.parameters.is_empty() && !failed &&
+                    should_report(&global_opts, &response, &raw.body, &validator) {
+                response.parameters = param_discovery::discover(&mut engine,
+                    &global_opts, &request);
+            }
+            let url = response.url.clone();
+            let reported = send_response(&dir_tx, &output_tx, &global_opts, response,
+                &raw.body, &validator, &checkpoint);
+
+            // The backups of each file are looked for once, and not for
+            // the backups themselves
+            if reported && !failed && !global_opts.backup_rules.is_empty() &&
+                    uri_gen.derived_from.is_none() && uri_gen.verb == global_opts.http_verbs[0] {
+                queue_backups(&backup_tx, &uri_gen, &global_opts, &request.word, url,
+                    &validator);
+            }
+            reported
+        };
+
+        // Directories are saved with the response which showed they exist
+        if let Some(response_saver) = &response_saver {
+            if reported && !failed {
+                response_saver.save(&request, word.as_ref().map(String::as_str), &raw);
+            }
         }
 
-        // Detect consecutive errors and stop the thread if the count is exceeded
-        if global_opts.max_errors != 0 {
-            if code == 0 {
-                consecutive_errors += 1;
-                if consecutive_errors >= global_opts.max_errors {
-                    println!("Thread scanning {} stopping due to multiple consecutive errors received", hostname);
-                    break;
+        // Try to get around the refused request with other verbs,
+        // and report any which were handled differently
+        if let Some(tamper_response) = tamper_response {
+            if reported {
+                let tampered = tamper(&mut engine, &global_opts, &request, &tamper_response);
+                for (request, mut response, raw) in tampered {
+                    response.is_directory = false;
+                    response.parent_depth = parent_depth;
+                    response.derived_from = uri_gen.derived_from.clone();
+                    let word = response.word.clone();
+                    let reported = send_response(&dir_tx, &output_tx, &global_opts,
+                        response, &raw.body, &validator, &checkpoint);
+
+                    if let Some(response_saver) = &response_saver {
+                        if reported {
+                            response_saver.save(&request, word.as_ref().map(String::as_str),
+                                &raw);
+                        }
+                    }
                 }
             }
-            else {
-                consecutive_errors = 0;
-            }
+        }
+
+        // Every response before the ones still pending has now been sent
+        uri_gen.update_progress(engine.pending());
+
+        // Stop the thread if the host has had too many consecutive errors
+        // These are counted across every thread scanning the host
+        if global_opts.max_errors != 0 &&
+                rate_limiter.consecutive_errors(&hostname) >= global_opts.max_errors {
+            println!("Thread scanning {} stopping due to multiple consecutive errors received", hostname);
+            break;
         }
 
         // Sleep if throttle is set
@@ -102,46 +204,164 @@ pub fn thread_spawn(dir_tx: mpsc::Sender<request::RequestResponse>,
     dir_tx.send(generate_end()).unwrap();
 }
 
-// Sends the given RequestResponse to the main thread
-// dependent on whitelist/blacklist settings and response code
+// Requests a path which was refused again with each other verb and an
+// X-HTTP-Method-Override header giving the refused verb, and for the first
+// verb also with each verb which isn't being scanned. Returns the responses
+// whose code differs from the original's, with the requests they were for
+fn tamper(engine: &mut RequestEngine, global_opts: &arg_parse::GlobalOpts,
+          request: &request::Request, response: &request::RequestResponse)
+    -> Vec<(request::Request, request::RequestResponse, request::RawResponse)> {
+    let mut requests = Vec::new();
+    for verb in HttpVerb::variants().iter() {
+        let verb = verb.parse::<HttpVerb>().unwrap();
+        if verb == request.verb { continue }
+
+        // The other scanned verbs are requested by their own jobs
+        if request.verb == global_opts.http_verbs[0] &&
+                !global_opts.http_verbs.contains(&verb) {
+            requests.push(request::Request { verb, ..request.clone() });
+        }
+        requests.push(request::Request {
+            verb,
+            method_override: Some(request.verb),
+            ..request.clone()
+        });
+    }
+
+    // These are queued behind the requests already in flight, so that
+    // a refused folder doesn't go over the concurrency limit
+    let responses = request::make_raw_requests(engine, requests.clone());
+
+    requests.into_iter().zip(responses)
+        .map(|(request, (tampered, raw))| (request, tampered, raw))
+        .filter(|(_, tampered, _)| tampered.error.is_none() && tampered.code != response.code)
+        .collect()
+}
+
+// Sends the main thread a job requesting the backups of a file found in
+// the folder being scanned, which uses the same not found definition
+fn queue_backups(backup_tx: &mpsc::Sender<wordlist::UriGenerator>,
+                 uri_gen: &wordlist::UriGenerator, global_opts: &arg_parse::GlobalOpts,
+                 word: &str, url: String,
+                 validator: &Option<validator_thread::TargetValidator>) {
+    let file = format!("{}{}{}", uri_gen.prefix, word, uri_gen.suffix);
+    let backups = backup::variants(&global_opts.backup_rules, &file);
+    if backups.is_empty() {
+        return;
+    }
+
+    backup_tx.send(wordlist::UriGenerator::for_backups(uri_gen.hostname.clone(),
+        uri_gen.verb, url, backups, uri_gen.parent_depth, validator.clone())).unwrap();
+}
+
+// Validates the directory being scanned again, returning the new
+// validator if its definition of a not found response has changed
+fn revalidate(engine: &mut RequestEngine, uri_gen: &wordlist::UriGenerator,
+    global_opts: &arg_parse::GlobalOpts, validator: &validator_thread::TargetValidator,
+    event_tx: &mpsc::Sender<ScanEvent>) -> Option<validator_thread::TargetValidator> {
+    let url = uri_gen.hostname.clone() + "/";
+    let new_validator = validator_thread::revalidate(&url, uri_gen.verb,
+        &uri_gen.prefix, &uri_gen.suffix, &global_opts.url_template_for(&uri_gen.hostname),
+        global_opts.validator_probes, engine)?;
+
+    if new_validator.is_equivalent(validator) {
+        return None;
+    }
+
+    println!("Nonexistent paths for {}{}{}*{} changed from {} to {}, updating",
+        validator_thread::verb_text(uri_gen.verb, global_opts), url, uri_gen.prefix,
+        uri_gen.suffix, validator.summary_text(), new_validator.summary_text());
+    event_tx.send(ScanEvent::ValidatorChanged {
+        url,
+        method: uri_gen.verb.method(),
+        prefix: uri_gen.prefix.clone(),
+        extension: uri_gen.suffix.clone(),
+        previous: validator.clone(),
+        validator: new_validator.clone()
+    }).unwrap();
+
+    Some(new_validator)
+}
+
+// Sends the given RequestResponse to the main thread if it should be
+// reported, returns true if it was sent
 fn send_response(dir_tx: &mpsc::Sender<request::RequestResponse>, 
     output_tx: &mpsc::Sender<request::RequestResponse>,
     global_opts: &arg_parse::GlobalOpts, response: request::RequestResponse,
-    validator_opt: &Option<validator_thread::TargetValidator>) {
+    body: &[u8], validator_opt: &Option<validator_thread::TargetValidator>,
+    checkpoint: &Option<Arc<Checkpoint>>) -> bool {
 
-    if response.is_directory {
+    if !should_report(global_opts, &response, body, validator_opt) {
+        return false
+    }
+
+    // Skip responses which were already found before the scan was resumed
+    if let Some(checkpoint) = checkpoint {
+        if !checkpoint.add_response(&response) {
+            return false
+        }
+    }
+
+    if response.is_directory && response.error.is_none() {
         dir_tx.send(response.clone()).unwrap();
-        output_tx.send(response).unwrap();
-        return
     }
+    output_tx.send(response).unwrap();
+    true
+}
 
-    let contains_code = global_opts.code_list.contains(&response.code);
+// Returns true if the response should be reported, dependent on
+// whitelist/blacklist settings, the response code and any filters
+fn should_report(global_opts: &arg_parse::GlobalOpts, response: &request::RequestResponse,
+    body: &[u8], validator_opt: &Option<validator_thread::TargetValidator>) -> bool {
 
-    if global_opts.whitelist && contains_code {
-        output_tx.send(response).unwrap();   
+    // Failed requests are always output so they can be scanned again
+    let output = if response.error.is_some() || response.is_directory {
+        true
     }
-    else if !global_opts.whitelist && !contains_code {
-        match validator_opt {
-            Some(validator) => {
-                if !validator.is_not_found(&response) {
-                    output_tx.send(response).unwrap();      
-                }
-            },
-            None => output_tx.send(response).unwrap()
+    else {
+        let contains_code = global_opts.code_list.contains(&response.code);
+
+        if global_opts.whitelist {
+            contains_code
         }
-    }
+        else if !contains_code {
+            match validator_opt {
+                Some(validator) => !validator.is_not_found(&response),
+                None => true
+            }
+        }
+        else { false }
+    };
+
+    if !output { return false }
+
+    // Then check the response against any filters and matchers
+    response.error.is_some() || response.is_directory ||
+        global_opts.filters.is_shown(response, body)
 }
 
 
-fn generate_end() -> request::RequestResponse {
+pub fn generate_end() -> request::RequestResponse {
     request::RequestResponse {
         url: String::from("END"),
+        verb: arg_parse::HttpVerb::Get,
+        method_override: None,
+        word: None,
         code: 0,
         content_len: 0,
+        words: 0,
+        lines: 0,
         is_directory:false,
         is_listable: false,
         redirect_url: String::from(""),
+        redirect_chain: None,
         found_from_listable: false,
-        parent_depth: 0
+        derived_from: None,
+        parent_depth: 0,
+        headers: request::ResponseHeaders::default(),
+        timing: request::RequestTiming::default(),
+        parameters: Vec::new(),
+        fingerprint: 0,
+        error: None
     }
 }
\ No newline at end of file
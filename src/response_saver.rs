@@ -0,0 +1,167 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    process::exit,
+    sync::Mutex,
+};
+use crate::arg_parse::HttpVerb;
+use crate::request::{RawResponse, Request};
+
+#[cfg(test)]
+mod tests;
+
+// The file listing which file each url was saved to
+const INDEX_FILENAME: &str = "index.txt";
+
+// Writes the raw headers and body of reported responses to a directory
+// tree mirroring the structure of their urls
+pub struct ResponseSaver {
+    directory: PathBuf,
+    index: Mutex<File>,
+}
+
+impl ResponseSaver {
+    // The index is appended to so that a resumed scan keeps the
+    // files saved before it was stopped
+    pub fn new(directory: &str) -> ResponseSaver {
+        let directory = PathBuf::from(directory);
+        let index = fs::create_dir_all(&directory)
+            .and_then(|_| OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(directory.join(INDEX_FILENAME)))
+            .unwrap_or_else(|error| {
+                println!("Creating the response directory \"{}\" failed: {}",
+                    directory.display(), error);
+                exit(2);
+            });
+
+        ResponseSaver {
+            directory,
+            index: Mutex::new(index),
+        }
+    }
+
+    // The word is given for responses which are told apart by it rather
+    // than by their url
+    pub fn save(&self, request: &Request, word: Option<&str>, raw: &RawResponse) {
+        let url = &request.url;
+        let relative_path = response_path(url, request.verb, request.method_override, word);
+        let path = self.directory.join(&relative_path);
+
+        let mut contents = raw.headers.join("\r\n").into_bytes();
+        contents.extend_from_slice(b"\r\n\r\n");
+        contents.extend_from_slice(&raw.body);
+
+        let result = fs::create_dir_all(path.parent().unwrap())
+            .and_then(|_| fs::write(&path, contents))
+            .and_then(|_| writeln!(self.index.lock().unwrap(), "{}\t{}",
+                url, relative_path.display()));
+
+        if let Err(error) = result {
+            println!("Saving the response from {} to \"{}\" failed: {}",
+                url, path.display(), error);
+        }
+    }
+}
+
+// Gives the path, relative to the response directory, that the response
+// from a url is saved to. Each host has its own folder, with each part of
+// the path as a subfolder and the last part, including any query string,
+// as the file name with .response appended. Directories are saved as
+// %2F.response within their folder. Verbs other than GET are added to the
+// file name after an @, and any word sent outside the url after a ~, both
+// of which are always encoded in the rest of the name
+pub fn response_path(url: &str, verb: HttpVerb, method_override: Option<HttpVerb>,
+                     word: Option<&str>) -> PathBuf {
+    let (scheme, rest) = match url.find("://") {
+        Some(index) => (&url[0..index], &url[index+3..]),
+        None => ("", url)
+    };
+
+    let (authority, path) = match rest.find(|c| c == '/' || c == '?') {
+        Some(index) => (&rest[0..index], rest[index..].trim_start_matches('/')),
+        None => (rest, "")
+    };
+
+    // Leave any username and password out of the folder name
+    let host = match authority.rfind('@') {
+        Some(index) => &authority[index+1..],
+        None => authority
+    };
+
+    let mut response_path = PathBuf::from(
+        encode_segment(&format!("{}_{}", scheme, host.replace(":", "_"))));
+
+    let mut segments: Vec<&str> = path.split('/').collect();
+    let filename = segments.pop().unwrap();
+    for segment in segments {
+        response_path.push(encode_segment(segment));
+    }
+
+    let mut filename = if filename.is_empty() {
+        String::from("%2F")
+    }
+    else {
+        encode_segment(filename)
+    };
+
+    if let Some(word) = word {
+        filename += &format!("~{}", encode_segment(word));
+    }
+
+    match method_override {
+        Some(method_override) => {
+            filename += &format!("@{}-as-{}", verb.method(), method_override.method());
+        }
+        None if verb != HttpVerb::Get => {
+            filename += &format!("@{}", verb.method());
+        }
+        None => {}
+    }
+
+    response_path.push(filename + ".response");
+
+    response_path
+}
+
+// Percent encodes everything other than letters, numbers, dots, dashes and
+// underscores, so that each segment is a single safe file name. Segments
+// which are empty or only dots are encoded so they can't refer to the
+// current or parent folder
+fn encode_segment(segment: &str) -> String {
+    if segment.is_empty() {
+        return String::from("%2F");
+    }
+
+    let only_dots = segment.chars().all(|c| c == '.');
+    segment.bytes()
+        .map(|byte| {
+            if !only_dots && (byte.is_ascii_alphanumeric() ||
+                    byte == b'.' || byte == b'-' || byte == b'_') {
+                (byte as char).to_string()
+            }
+            else {
+                format!("%{:02X}", byte)
+            }
+        })
+        .collect()
+}
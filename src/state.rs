@@ -0,0 +1,145 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::BufReader,
+    process::exit,
+    sync::Mutex,
+};
+use serde::{Serialize, Deserialize};
+use crate::arg_parse::HttpVerb;
+use crate::request::RequestResponse;
+use crate::wordlist::JobState;
+
+#[cfg(test)]
+mod tests;
+
+// Everything needed to carry on with a scan after it was stopped
+#[derive(Serialize, Deserialize)]
+pub struct ScanState {
+    // The command line arguments the scan was started with
+    pub args: Vec<String>,
+    pub wordlist_len: usize,
+    pub jobs: Vec<JobState>,
+    pub responses: Vec<RequestResponse>,
+    // Directories which have been validated and queued for scanning
+    pub handled_directories: Vec<String>,
+}
+
+impl ScanState {
+    pub fn load(filename: &str) -> ScanState {
+        let file = File::open(filename)
+            .unwrap_or_else(|error| {
+                println!("Opening state file \"{}\" failed: {}", filename, error);
+                exit(2);
+            });
+
+        serde_json::from_reader(BufReader::new(file))
+            .unwrap_or_else(|error| {
+                println!("Reading state file \"{}\" failed: {}", filename, error);
+                exit(2);
+            })
+    }
+}
+
+// Responses are identified by their url, the verbs they were requested
+// with and the word sent to a fixed url, as the same url can be reported
+// for each verb and word
+type ResponseKey = (String, HttpVerb, Option<HttpVerb>, Option<String>);
+
+fn response_key(response: &RequestResponse) -> ResponseKey {
+    (response.url.clone(), response.verb, response.method_override, response.word.clone())
+}
+
+struct Discovered {
+    responses: Vec<RequestResponse>,
+    keys: HashSet<ResponseKey>,
+}
+
+// Collects the progress of the scan as it runs so that it can be
+// periodically written to the state file
+pub struct Checkpoint {
+    filename: String,
+    args: Vec<String>,
+    wordlist_len: usize,
+    discovered: Mutex<Discovered>,
+    handled_directories: Mutex<HashSet<String>>,
+}
+
+impl Checkpoint {
+    pub fn new(filename: String, args: Vec<String>, wordlist_len: usize,
+               responses: Vec<RequestResponse>,
+               handled_directories: Vec<String>) -> Checkpoint {
+        let keys = responses.iter()
+            .map(response_key)
+            .collect();
+
+        Checkpoint {
+            filename,
+            args,
+            wordlist_len,
+            discovered: Mutex::new(Discovered { responses, keys }),
+            handled_directories: Mutex::new(handled_directories.into_iter().collect()),
+        }
+    }
+
+    // Records a response which is going to be output, returns false if
+    // it was already found before the scan was resumed
+    pub fn add_response(&self, response: &RequestResponse) -> bool {
+        let mut discovered = self.discovered.lock().unwrap();
+        if !discovered.keys.insert(response_key(response)) {
+            return false;
+        }
+        discovered.responses.push(response.clone());
+        true
+    }
+
+    pub fn mark_handled(&self, url: &str) {
+        self.handled_directories.lock().unwrap().insert(String::from(url));
+    }
+
+    pub fn is_handled(&self, url: &str) -> bool {
+        self.handled_directories.lock().unwrap().contains(url)
+    }
+
+    // Writes the state to a temporary file first and then moves it into
+    // place, so that the scan can't be stopped with a half written file
+    pub fn save(&self, jobs: Vec<JobState>) {
+        let state = ScanState {
+            args: self.args.clone(),
+            wordlist_len: self.wordlist_len,
+            jobs,
+            responses: self.discovered.lock().unwrap().responses.clone(),
+            handled_directories: self.handled_directories.lock().unwrap()
+                .iter().cloned().collect(),
+        };
+
+        let temp_filename = format!("{}.tmp", self.filename);
+        let result = serde_json::to_vec(&state)
+            .map_err(|error| error.to_string())
+            .and_then(|contents| fs::write(&temp_filename, contents)
+                .map_err(|error| error.to_string()))
+            .and_then(|_| fs::rename(&temp_filename, &self.filename)
+                .map_err(|error| error.to_string()));
+
+        if let Err(error) = result {
+            println!("Saving the scan state to \"{}\" failed: {}", self.filename, error);
+        }
+    }
+}
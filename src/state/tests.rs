@@ -0,0 +1,107 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{fs, sync::Arc};
+use crate::arg_parse::HttpVerb;
+use crate::request::fabricate_request_response;
+use crate::wordlist::{JobState, UriGenerator};
+use crate::wordlist_store::Wordlist;
+use super::{Checkpoint, ScanState};
+
+fn job() -> JobState {
+    let wordlist = Arc::new(Wordlist::from_words(&[String::from("admin")]));
+    UriGenerator::new(String::from("http://example.com/"), HttpVerb::Post,
+        String::from("old_"), String::from(".php"), None, wordlist, Arc::new(Vec::new()),
+        1, 3, 2, None).job_state()
+}
+
+#[test]
+fn state_is_saved_and_loaded() {
+    let directory = tempfile::tempdir().unwrap();
+    let filename = directory.path().join("scan.state").to_str().unwrap().to_string();
+
+    let found = fabricate_request_response(String::from("http://example.com/admin/"), true, false);
+    let checkpoint = Checkpoint::new(filename.clone(), vec![String::from("dirble"),
+        String::from("http://example.com")], 1234, vec![found], Vec::new());
+    assert!(checkpoint.add_response(&fabricate_request_response(
+        String::from("http://example.com/login"), false, false)));
+    checkpoint.mark_handled("http://example.com/admin/");
+    checkpoint.save(vec![job()]);
+
+    // The temporary file is moved into place
+    assert!(!directory.path().join("scan.state.tmp").exists());
+
+    let state = ScanState::load(&filename);
+    assert_eq!(state.args, vec!["dirble", "http://example.com"]);
+    assert_eq!(state.wordlist_len, 1234);
+    assert_eq!(state.handled_directories, vec!["http://example.com/admin/"]);
+    let urls: Vec<&str> = state.responses.iter().map(|response| response.url.as_str()).collect();
+    assert_eq!(urls, vec!["http://example.com/admin/", "http://example.com/login"]);
+
+    assert_eq!(state.jobs.len(), 1);
+    let job = &state.jobs[0];
+    assert_eq!(job.hostname, "http://example.com");
+    assert_eq!(job.verb, HttpVerb::Post);
+    assert_eq!((job.prefix.as_str(), job.suffix.as_str()), ("old_", ".php"));
+    assert_eq!((job.current_index, job.step_size, job.parent_depth), (1, 3, 2));
+}
+
+#[test]
+fn failed_saves_keep_the_previous_state() {
+    let directory = tempfile::tempdir().unwrap();
+    let filename = directory.path().join("scan.state").to_str().unwrap().to_string();
+    let checkpoint = Checkpoint::new(filename.clone(), Vec::new(), 10, Vec::new(), Vec::new());
+    checkpoint.save(vec![job()]);
+
+    // The temporary file can't be written over a folder, so the state
+    // file should be left as it was rather than half written
+    fs::create_dir(format!("{}.tmp", filename)).unwrap();
+    checkpoint.save(Vec::new());
+    assert_eq!(ScanState::load(&filename).jobs.len(), 1);
+}
+
+#[test]
+fn responses_are_only_added_once() {
+    let response = fabricate_request_response(String::from("http://example.com/a"), false, false);
+    let checkpoint = Checkpoint::new(String::from("unused"), Vec::new(), 0,
+        vec![response.clone()], Vec::new());
+
+    // Responses found before resuming aren't reported again
+    assert!(!checkpoint.add_response(&response));
+
+    // The same url with a different verb is a different result
+    let mut post = response.clone();
+    post.verb = HttpVerb::Post;
+    assert!(checkpoint.add_response(&post));
+    assert!(!checkpoint.add_response(&post));
+
+    let mut overridden = post.clone();
+    overridden.method_override = Some(HttpVerb::Get);
+    assert!(checkpoint.add_response(&overridden));
+}
+
+#[test]
+fn old_job_states_are_loaded() {
+    // States saved before verbs, backups and their words were added
+    let job: JobState = serde_json::from_str(r#"{"hostname":"http://example.com",
+        "prefix":"","suffix":".php","current_index":5,"step_size":3,"parent_depth":0,
+        "validator":null}"#).unwrap();
+    assert_eq!(job.verb, HttpVerb::Get);
+    assert_eq!(job.derived_from, None);
+    assert_eq!(job.words, None);
+    assert_eq!(job.current_index, 5);
+}
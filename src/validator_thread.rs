@@ -16,37 +16,66 @@
 // along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::request;
-use std::sync::{Arc, mpsc::self};
+use crate::content_parse;
+use std::sync::{Arc, mpsc::self, atomic::{AtomicBool, Ordering}};
+use std::collections::HashMap;
+use std::hash::Hash;
 use std::fmt;
-use crate::arg_parse;
-use curl::easy::Easy2;
+use crate::arg_parse::{self, HttpVerb};
+use crate::rate_limit::RateLimiter;
+use crate::request_engine::RequestEngine;
+use crate::output::ScanEvent;
+use crate::wordlist;
+use serde::{Serialize, Deserialize};
 extern crate rand;
 
 use rand::{thread_rng, Rng};
 use rand::distributions::Alphanumeric;
+use percent_encoding::{utf8_percent_encode, DEFAULT_ENCODE_SET};
+
+#[cfg(test)]
+mod tests;
 
 // Struct for passing information back to the main thread
 pub struct DirectoryInfo {
     pub url:String,
+    // The validator for words with no prefix or extension
     pub validator:Option<TargetValidator>,
+    // Validators for each other verb, prefix and extension combination,
+    // keyed by the verb, the prefix then the extension
+    pub validators: HashMap<(HttpVerb, String, String), TargetValidator>,
     pub parent_depth: u32,
 }
 
 impl DirectoryInfo {
     pub fn new(url: String, validator: Option<TargetValidator>, 
+                validators: HashMap<(HttpVerb, String, String), TargetValidator>,
                 parent_depth:u32) -> DirectoryInfo {
         DirectoryInfo {
             url,
             validator,
+            validators,
             parent_depth
         }
     }
 
+    // Returns the validator to use for words with the given verb, prefix
+    // and extension, falling back to the validator for bare words with the
+    // verb and then to the validator for bare words
+    pub fn validator_for(&self, verb: HttpVerb, prefix: &str, extension: &str)
+        -> Option<TargetValidator> {
+        self.validators.get(&(verb, String::from(prefix), String::from(extension)))
+            .or_else(|| self.validators.get(&(verb, String::new(), String::new())))
+            .cloned()
+            .or_else(|| self.validator.clone())
+    }
+
     // Used to inform the main thread that a request thread ended
     pub fn generate_end() -> DirectoryInfo {
         DirectoryInfo {
             url:String::from("END"),
             validator:None,
+            validators: HashMap::new(),
             parent_depth: 0
         }
     }
@@ -54,24 +83,29 @@ impl DirectoryInfo {
 
 // Struct containing information to determine if a response
 // was not found for a directory
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct TargetValidator {
     response_code:u32,
     response_len:Option<i32>,
     diff_response_len:Option<i32>,
     redirect_url:Option<String>,
+    // Simhash of the not found page's body, used instead of the
+    // lengths when the not found pages were similar to each other
+    fingerprint:Option<u64>,
     pub validator_alert: Option<ValidatorAlert>
 }
 
 impl TargetValidator {
      pub fn new(response_code: u32, response_len: Option<i32>, 
                 diff_response_len: Option<i32>, redirect_url: Option<String>,
+                fingerprint: Option<u64>,
                 validator_alert: Option<ValidatorAlert>) -> TargetValidator{
         TargetValidator {
             response_code,
             response_len,
             diff_response_len,
             redirect_url,
+            fingerprint,
             validator_alert
         }
      }
@@ -89,6 +123,12 @@ impl TargetValidator {
             return redirect_url == &response.redirect_url;
         }
 
+        // If the not found pages had similar content then the response
+        // is not found if its content is similar too
+        if let Some(fingerprint) = self.fingerprint {
+            return content_parse::is_similar(fingerprint, response.fingerprint);
+        }
+
         // If there is a length in the validator then check against that,
         // otherwise it is "not found"
         if let Some(size) = self.response_len {
@@ -104,6 +144,23 @@ impl TargetValidator {
         }
      }
 
+     // Returns true if the other validator has the same definition of
+     // a not found response
+     pub fn is_equivalent(&self, other: &TargetValidator) -> bool {
+        let fingerprints_match = match (self.fingerprint, other.fingerprint) {
+            (Some(fingerprint), Some(other_fingerprint)) =>
+                content_parse::is_similar(fingerprint, other_fingerprint),
+            (None, None) => true,
+            _ => false
+        };
+
+        self.response_code == other.response_code
+            && self.response_len == other.response_len
+            && self.diff_response_len == other.diff_response_len
+            && self.redirect_url == other.redirect_url
+            && fingerprints_match
+     }
+
      // Return a string summary of this validator's definition
      // of a not found response
      pub fn summary_text(&self)  -> String {
@@ -121,6 +178,10 @@ impl TargetValidator {
             output += &format!("|DIFF_SIZE:{}", length);
         }
 
+        if let Some(fingerprint) = self.fingerprint {
+            output += &format!("|SIMHASH:{:016x}", fingerprint);
+        }
+
         output + ")"
      }
 
@@ -137,9 +198,8 @@ impl TargetValidator {
                 ValidatorAlert::Code403 => {
                     return scan_opts.scan_403
                 }
-                // Placeholder branch for future use
-                ValidatorAlert::RedirectToHTTPS => { 
-                    return true 
+                ValidatorAlert::RedirectToHTTPS(_) => {
+                    return scan_opts.scan_redirects
                 }
             }
         }
@@ -160,11 +220,23 @@ impl TargetValidator {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum ValidatorAlert {
     Code401,
     Code403,
-    RedirectToHTTPS
+    // Every path redirects to the same path on the contained HTTPS origin
+    RedirectToHTTPS(String)
+}
+
+impl ValidatorAlert {
+    // A short description of why the directory isn't being scanned
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ValidatorAlert::Code401 => "directory returns 401",
+            ValidatorAlert::Code403 => "directory returns 403",
+            ValidatorAlert::RedirectToHTTPS(_) => "directory redirects to HTTPS"
+        }
+    }
 }
 
 impl fmt::Display for ValidatorAlert {
@@ -182,18 +254,23 @@ impl fmt::Display for ValidatorAlert {
                     Use the --scan-403 flag to scan this directory,\n    \
                     or provide valid session token or credentials.")
             },
-            // Placeholder branch
-            ValidatorAlert::RedirectToHTTPS => {
-                write!(f, 
-                    "The directory redirected to HTTPS")
+            ValidatorAlert::RedirectToHTTPS(origin) => {
+                write!(f,
+                    "\n    Every path in the directory redirects to {}.\n    \
+                    Use the --upgrade-https flag to scan the HTTPS directory instead,\n    \
+                    or the --scan-redirects flag to scan this directory anyway.", origin)
             }
         }
     }
 }
 
 pub fn validator_thread(rx: mpsc::Receiver<request::RequestResponse>, main_tx: mpsc::Sender<Option<DirectoryInfo>>,
-    global_opts:Arc<arg_parse::GlobalOpts>)
+    event_tx: mpsc::Sender<ScanEvent>, global_opts:Arc<arg_parse::GlobalOpts>,
+    rate_limiter: Arc<RateLimiter>, stopping: Arc<AtomicBool>)
 {
+    // Up to three of the validation probes for a directory are made at once
+    let mut engine = RequestEngine::new(&global_opts, 3, rate_limiter);
+
     loop {
         // Get a RequestResponse from the receiver
         if let Ok(response) = rx.try_recv() {
@@ -205,6 +282,11 @@ pub fn validator_thread(rx: mpsc::Receiver<request::RequestResponse>, main_tx: m
             else if response.url == "MAIN ENDING" {
                 break;
             }
+            // Directories found while the scan is stopping won't be
+            // scanned, so don't make requests to validate them
+            else if stopping.load(Ordering::SeqCst) {
+                main_tx.send(None).unwrap();
+            }
             else {
                 // Don't do anything if it's somehow not a directory
                 // Also don't do anything if it's listable and we aren't scanning those
@@ -236,28 +318,42 @@ pub fn validator_thread(rx: mpsc::Receiver<request::RequestResponse>, main_tx: m
                 // return a validator of None
                 // The validator is unused if whitelisting is enabled
                 if global_opts.disable_validator || global_opts.whitelist {
-                    let directory_info = DirectoryInfo::new(response.url, None, response.parent_depth);
+                    let directory_info = DirectoryInfo::new(response.url, None,
+                        HashMap::new(), response.parent_depth);
                     main_tx.send(Some(directory_info)).unwrap();  
                     continue;
                 }
 
-                // Generate an easy and make 3 random requests to the folder
-                let mut easy = request::generate_easy(&global_opts);
-                let responses = make_requests(response.url.clone(), &mut easy);
+                let mut url = response.url;
+                let mut validation = validate_directory(&url, &global_opts, &event_tx, &mut engine);
+
+                // If every path in the folder redirects to HTTPS, then
+                // validate and scan the HTTPS folder instead if enabled
+                if global_opts.scan_opts.upgrade_https {
+                    let https_url = validation.as_ref()
+                        .and_then(|(validator, _)| upgraded_url(validator, &url));
 
-                //Get a validator
-                let validator_option = determine_not_found(responses);
+                    if let Some(https_url) = https_url {
+                        println!("{} redirects to HTTPS, scanning {} instead", url, https_url);
+                        url = https_url;
+                        validation = validate_directory(&url, &global_opts, &event_tx, &mut engine);
+                    }
+                }
 
                 // If there is a validator then wrap it in a DirectoryInfo and send to main
-                if let Some(validator) = validator_option {
-                    println!("Detected nonexistent paths for {} are {}", &response.url, validator.summary_text());
-                    let directory_info = DirectoryInfo::new(response.url, Some(validator), response.parent_depth);
+                if let Some((validator, validators)) = validation {
+                    let directory_info = DirectoryInfo::new(url, Some(validator),
+                        validators, response.parent_depth);
                     main_tx.send(Some(directory_info)).unwrap();
                 }
                 // If there isn't a validator then send a none back to main
                 // This will be ignored but is necessary during validation of initial directories
                 else {
-                    println!("{} errored too often during validation, skipping scanning", response.url);
+                    println!("{} errored too often during validation, skipping scanning", url);
+                    event_tx.send(ScanEvent::SkippedDirectory {
+                        url,
+                        reason: String::from("errored too often during validation")
+                    }).unwrap();
                     main_tx.send(None).unwrap();
                 }
             }
@@ -265,58 +361,165 @@ pub fn validator_thread(rx: mpsc::Receiver<request::RequestResponse>, main_tx: m
     }
 }
 
-// Makes a set of 3 requests to random strings of different lengths in the given folder
-fn make_requests(mut base_url:String, easy: &mut Easy2<request::Collector>) -> Vec<request::RequestResponse> {
-    let mut response_vector:Vec<request::RequestResponse> = Vec::new();
+// Determines the not found response of a folder for bare words and
+// for each verb, prefix and extension combination, returns None if the
+// bare words errored during validation
+fn validate_directory(url: &str, global_opts: &arg_parse::GlobalOpts,
+                      event_tx: &mpsc::Sender<ScanEvent>, engine: &mut RequestEngine)
+                      -> Option<(TargetValidator, HashMap<(HttpVerb, String, String), TargetValidator>)> {
+    // Servers often handle each verb and extension differently, so the
+    // folder is validated for every combination
+    let mut modifiers = Vec::new();
+    for verb in &global_opts.http_verbs {
+        for prefix in &global_opts.prefixes {
+            for extension in &global_opts.extensions {
+                modifiers.push((*verb, prefix.clone(), extension.clone()));
+            }
+        }
+    }
+
+    // Make random requests to the folder for each pair
+    let responses = make_requests(String::from(url), &modifiers,
+        &global_opts.url_template_for(url), global_opts.validator_probes, engine);
+
+    //Get a validator for each combination
+    let mut validator_option = None;
+    let mut validators = HashMap::new();
+    for ((verb, prefix, extension), responses) in modifiers.into_iter().zip(responses) {
+        let validator = determine_not_found(responses);
+
+        // The prefixes and extensions are sorted, so the combination of
+        // the first verb with neither is always first
+        if verb == global_opts.http_verbs[0] && prefix.is_empty() && extension.is_empty() {
+            validator_option = validator;
+            if let Some(validator) = &validator_option {
+                println!("Detected nonexistent paths for {} are {}",
+                    url, validator.summary_text());
+                event_tx.send(ScanEvent::Validator {
+                    url: String::from(url),
+                    method: verb.method(),
+                    prefix,
+                    extension,
+                    validator: validator.clone()
+                }).unwrap();
+            }
+            continue;
+        }
+
+        // Combinations whose validation errored use the bare validator
+        if let Some(validator) = validator {
+            // Only mention combinations which behave differently
+            let bare_summary = validator_option.as_ref()
+                .map(TargetValidator::summary_text);
+            if bare_summary != Some(validator.summary_text()) {
+                println!("Detected nonexistent paths for {}{}{}*{} are {}",
+                    verb_text(verb, global_opts), url, prefix, extension,
+                    validator.summary_text());
+            }
+            event_tx.send(ScanEvent::Validator {
+                url: String::from(url),
+                method: verb.method(),
+                prefix: prefix.clone(),
+                extension: extension.clone(),
+                validator: validator.clone()
+            }).unwrap();
+            validators.insert((verb, prefix, extension), validator);
+        }
+    }
+
+    validator_option.map(|validator| (validator, validators))
+}
+
+// Validates a folder again for a single verb, prefix and extension, used
+// to check that the not found response hasn't changed during a scan
+pub fn revalidate(url: &str, verb: HttpVerb, prefix: &str, extension: &str,
+                  url_template: &Option<String>, probe_count: u32, engine: &mut RequestEngine)
+                  -> Option<TargetValidator> {
+    let modifiers = vec![(verb, String::from(prefix), String::from(extension))];
+    let responses = make_requests(String::from(url), &modifiers, url_template,
+        probe_count, engine);
+    determine_not_found(responses.into_iter().next().unwrap())
+}
 
+// Makes a set of requests to random strings of different lengths in the
+// given folder for each verb, prefix and extension, or put into the url
+// template if there is one. The engine limits how many are made at once
+fn make_requests(mut base_url:String, modifiers: &Vec<(HttpVerb, String, String)>,
+                 url_template: &Option<String>, probe_count: u32, engine: &mut RequestEngine)
+                 -> Vec<Vec<request::RequestResponse>> {
     if !base_url.ends_with("/")
     {
         base_url += "/";
     }
 
-    for i in 1..=3 {
-        let url = format!("{}{}", base_url, rand_string(10*i));
-        response_vector.push(request::make_request(easy, url));
+    let mut requests = Vec::new();
+    for (verb, prefix, extension) in modifiers {
+        for i in 1..=probe_count as usize {
+            let word = rand_string(10*i);
+            let url = match url_template {
+                Some(template) => wordlist::url_from_template(template,
+                    &format!("{}{}{}", prefix, word, extension)),
+                None => {
+                    let url = format!("{}{}{}{}", base_url, prefix, word, extension);
+                    utf8_percent_encode(&url, DEFAULT_ENCODE_SET).to_string()
+                }
+            };
+            requests.push(request::Request::for_word(url, *verb, &word));
+        }
     }
 
-    response_vector
-
+    request::make_requests(engine, requests)
+        .chunks(probe_count as usize)
+        .map(|responses| responses.to_vec())
+        .collect()
 }
 
 
-// Generate a target validator for a given set of 3 responses
+// The verb to mention before a url when describing its validator, which
+// is left out for the first verb
+#[inline]
+pub fn verb_text(verb: HttpVerb, global_opts: &arg_parse::GlobalOpts) -> String {
+    if verb == global_opts.http_verbs[0] { String::new() }
+    else { format!("{} ", verb.method()) }
+}
+
+// Generate a target validator for a given set of responses to random paths
+// Each part of the not found definition is the value most of the
+// responses agreed on, and is left out if there wasn't a majority
 fn determine_not_found(responses:Vec<request::RequestResponse>) -> Option<TargetValidator> {
 
-    if responses.len() < 3 {
-        return Some(TargetValidator::new(404, None, None, None, None))
+    if responses.len() < 2 {
+        return Some(TargetValidator::new(404, None, None, None, None, None))
     }
 
     let mut validator_alert = None;
 
-    let mut code = 404;
-    if responses[0].code == responses[1].code 
-            || responses[0].code == responses[2].code {
-        code = responses[0].code;
-    }
-    else if responses[1].code == responses[2].code {
-        code = responses[1].code;
-    }
+    let code = majority(responses.iter().map(|response| response.code))
+        .unwrap_or(404);
+
+    // Only responses with the not found code are used for the rest
+    let responses: Vec<request::RequestResponse> = responses.into_iter()
+        .filter(|response| response.code == code)
+        .collect();
 
     match code {
         0 => {
             return None;
         }
-        301 | 302 => {
-            let mut redirect_url = None;
-            if responses[0].redirect_url == responses[1].redirect_url
-                    || responses[0].redirect_url == responses[2].redirect_url {
-                redirect_url = Some(responses[0].redirect_url.clone());
-            }
-            else if responses[1].redirect_url == responses[2].redirect_url {
-                redirect_url = Some(responses[1].redirect_url.clone());
+        301 | 302 | 303 | 307 | 308 => {
+            // Check if every path is being redirected to the same
+            // path on an HTTPS origin
+            let https_origin = majority(responses.iter()
+                .map(|response| https_origin(&response.url, &response.redirect_url)))
+                .and_then(|origin| origin);
+            if let Some(origin) = https_origin {
+                validator_alert = Some(ValidatorAlert::RedirectToHTTPS(origin));
             }
 
-            return Some(TargetValidator::new(code, None, None, redirect_url, None))
+            let redirect_url = majority(responses.iter()
+                .map(|response| response.redirect_url.clone()));
+
+            return Some(TargetValidator::new(code, None, None, redirect_url, None, validator_alert))
         }
         401 => {
             validator_alert = Some(ValidatorAlert::Code401);
@@ -325,43 +528,95 @@ fn determine_not_found(responses:Vec<request::RequestResponse>) -> Option<Target
             validator_alert = Some(ValidatorAlert::Code403);
         }
         404 => {
-            return Some(TargetValidator::new(code, None, None, None, None));
+            return Some(TargetValidator::new(code, None, None, None, None, None));
         }
         _ => {}
     }
 
-    let mut response_size = None;
-    if responses[0].content_len == responses[1].content_len
-            || responses[0].content_len == responses[2].content_len {
-        response_size = Some(responses[0].content_len as i32);
-    }
-    else if responses[1].content_len == responses[2].content_len {
-        response_size = Some(responses[1].content_len as i32);
+    // Pages which differ slightly with each request are matched by
+    // the similarity of their content rather than their length
+    // The fingerprint used is the one similar to the most responses
+    let (fingerprint, similar_count) = responses.iter()
+        .map(|response| {
+            let similar_count = responses.iter()
+                .filter(|other| content_parse::is_similar(response.fingerprint, other.fingerprint))
+                .count();
+            (response.fingerprint, similar_count)
+        })
+        .max_by_key(|(_, similar_count)| *similar_count)
+        .unwrap();
+
+    if similar_count * 2 > responses.len() {
+        return Some(TargetValidator::new(code, None, None, None, Some(fingerprint), validator_alert));
     }
 
+    let response_size = majority(responses.iter()
+        .map(|response| response.content_len as i32));
+
     let mut diff_response_size = None;
     if response_size == None {
-        let diff_0 = ((responses[0].content_len as i32) - responses[0].url.len() as i32).abs();
-        let diff_1 = ((responses[1].content_len as i32) - responses[1].url.len() as i32).abs();
-        let diff_2 = ((responses[2].content_len as i32) - responses[2].url.len() as i32).abs();
+        diff_response_size = majority(responses.iter()
+            .map(|response| (response.content_len as i32 - response.url.len() as i32).abs()));
+    }
 
-        if diff_0 == diff_1
-            || diff_0 == diff_2 {
-            diff_response_size = Some(diff_0);
-        }
-        else if diff_1 == diff_2 {
-            diff_response_size = Some(diff_1);
-        }
+    Some(TargetValidator::new(code, response_size, diff_response_size, None, None, validator_alert))
+}
+
+// If the redirect is to the same path on an HTTPS origin, returns
+// that origin, for example https://example.com
+fn https_origin(url: &str, redirect_url: &str) -> Option<String> {
+    if !url.starts_with("http://") || !redirect_url.starts_with("https://") {
+        return None;
     }
 
-    Some(TargetValidator::new(code, response_size, diff_response_size, None, validator_alert))
+    let (_, path) = split_origin(url);
+    let (origin, redirect_path) = split_origin(redirect_url);
+
+    if path == redirect_path {
+        Some(String::from(origin))
+    }
+    else {
+        None
+    }
+}
 
+// The url of the same folder on the HTTPS origin every path in the folder
+// redirects to, if the validator found that they all do
+fn upgraded_url(validator: &TargetValidator, url: &str) -> Option<String> {
+    match &validator.validator_alert {
+        Some(ValidatorAlert::RedirectToHTTPS(origin)) =>
+            Some(format!("{}{}", origin, split_origin(url).1)),
+        _ => None
+    }
+}
+
+// Splits a url into its scheme and host, and the path after them
+fn split_origin(url: &str) -> (&str, &str) {
+    let host_start = url.find("://").map(|index| index + 3).unwrap_or(0);
+    match url[host_start..].find('/') {
+        Some(index) => url.split_at(host_start + index),
+        None => (url, "")
+    }
+}
+
+// Returns the most common of the values, if more than half of them are equal to it
+fn majority<T: Eq + Hash>(values: impl Iterator<Item = T>) -> Option<T> {
+    let mut counts: HashMap<T, usize> = HashMap::new();
+    let mut total = 0;
+    for value in values {
+        *counts.entry(value).or_insert(0) += 1;
+        total += 1;
+    }
 
+    counts.into_iter()
+        .max_by_key(|(_, count)| *count)
+        .filter(|(_, count)| count * 2 > total)
+        .map(|(value, _)| value)
 }
 
 // Based on https://rust-lang-nursery.github.io/rust-cookbook/algorithms/randomness.html
 // Generates a string of alphanumeric characters of the given length
-fn rand_string(length: usize) -> String {
+pub fn rand_string(length: usize) -> String {
     thread_rng().sample_iter(&Alphanumeric)
         .take(length).collect()
 }
\ No newline at end of file
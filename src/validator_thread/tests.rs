@@ -0,0 +1,180 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::request::{fabricate_request_response, RequestResponse};
+use super::{determine_not_found, https_origin, majority, split_origin, upgraded_url,
+    TargetValidator, ValidatorAlert};
+
+// Fingerprints far enough apart that the pages aren't similar
+const DIFFERENT_PAGES: [u64; 3] = [0, 0xffff_ffff_0000_0000, 0x0000_0000_ffff_ffff];
+
+fn response(path: &str, code: u32, size: usize, fingerprint: u64) -> RequestResponse {
+    let mut response = fabricate_request_response(format!("http://example.com/{}", path),
+        false, false);
+    response.code = code;
+    response.content_len = size;
+    response.fingerprint = fingerprint;
+    response
+}
+
+#[test]
+fn majority_needs_more_than_half() {
+    assert_eq!(majority(vec![1, 1, 2].into_iter()), Some(1));
+    assert_eq!(majority(vec![3, 3, 3].into_iter()), Some(3));
+    // Ties and splits without a majority have no winner
+    assert_eq!(majority(vec![1, 1, 2, 2].into_iter()), None);
+    assert_eq!(majority(vec![1, 2, 3].into_iter()), None);
+    assert_eq!(majority(vec![1, 1, 2, 3].into_iter()), None);
+    assert_eq!(majority(Vec::<u32>::new().into_iter()), None);
+}
+
+#[test]
+fn too_few_responses_default_to_404() {
+    let validator = determine_not_found(vec![response("a", 200, 10, 0)]).unwrap();
+    assert_eq!(validator.summary_text(), "(CODE:404)");
+    let validator = determine_not_found(Vec::new()).unwrap();
+    assert_eq!(validator.summary_text(), "(CODE:404)");
+}
+
+#[test]
+fn errored_validation_has_no_validator() {
+    let responses = vec![response("a", 0, 0, 0), response("bb", 0, 0, 0),
+        response("ccc", 404, 0, 0)];
+    assert!(determine_not_found(responses).is_none());
+}
+
+#[test]
+fn code_without_majority_defaults_to_404() {
+    let responses = vec![response("a", 200, 10, 0), response("bb", 500, 10, 0),
+        response("ccc", 0, 0, 0)];
+    assert_eq!(determine_not_found(responses).unwrap().summary_text(), "(CODE:404)");
+}
+
+#[test]
+fn similar_pages_are_fingerprinted() {
+    // One differing response doesn't stop the others being used
+    let responses = vec![response("a", 200, 10, DIFFERENT_PAGES[1]),
+        response("bb", 200, 12, DIFFERENT_PAGES[1] ^ 1), response("ccc", 200, 14, 0)];
+    let validator = determine_not_found(responses).unwrap();
+    assert_eq!(validator.response_code, 200);
+    assert!(validator.fingerprint.is_some());
+    assert_eq!(validator.response_len, None);
+}
+
+#[test]
+fn different_pages_use_their_size() {
+    let responses = vec![response("a", 200, 10, DIFFERENT_PAGES[0]),
+        response("bb", 200, 10, DIFFERENT_PAGES[1]), response("ccc", 200, 11, DIFFERENT_PAGES[2])];
+    let validator = determine_not_found(responses).unwrap();
+    assert_eq!(validator.summary_text(), "(CODE:200|SIZE:10)");
+
+    // Pages which echo the path differ in size by the length of the url
+    let responses = vec![response("a", 200, 100, DIFFERENT_PAGES[0]),
+        response("bb", 200, 101, DIFFERENT_PAGES[1]), response("ccc", 200, 102, DIFFERENT_PAGES[2])];
+    let validator = determine_not_found(responses).unwrap();
+    assert_eq!(validator.response_len, None);
+    assert_eq!(validator.diff_response_len, Some(100 - "http://example.com/a".len() as i32));
+
+    // Without either there is only the code
+    let responses = vec![response("a", 200, 5, DIFFERENT_PAGES[0]),
+        response("bb", 200, 50, DIFFERENT_PAGES[1]), response("ccc", 200, 500, DIFFERENT_PAGES[2])];
+    assert_eq!(determine_not_found(responses).unwrap().summary_text(), "(CODE:200)");
+}
+
+#[test]
+fn refused_folders_are_flagged() {
+    let responses = vec![response("a", 403, 10, 0), response("bb", 403, 10, 0),
+        response("ccc", 404, 10, 0)];
+    let validator = determine_not_found(responses).unwrap();
+    assert_eq!(validator.response_code, 403);
+    match validator.validator_alert {
+        Some(ValidatorAlert::Code403) => {},
+        _ => panic!("403 folders should be flagged")
+    }
+}
+
+fn redirect(url: &str, redirect_url: &str) -> RequestResponse {
+    let mut response = fabricate_request_response(String::from(url), false, false);
+    response.code = 301;
+    response.redirect_url = String::from(redirect_url);
+    response
+}
+
+#[test]
+fn origins_are_split_from_paths() {
+    assert_eq!(split_origin("http://example.com/admin/a"), ("http://example.com", "/admin/a"));
+    assert_eq!(split_origin("https://example.com:8443/"), ("https://example.com:8443", "/"));
+    assert_eq!(split_origin("http://example.com"), ("http://example.com", ""));
+    assert_eq!(split_origin("http://example.com/a?b=/c"), ("http://example.com", "/a?b=/c"));
+}
+
+#[test]
+fn redirects_to_the_same_path_over_https_are_detected() {
+    assert_eq!(https_origin("http://example.com/admin/abc", "https://example.com/admin/abc"),
+        Some(String::from("https://example.com")));
+    // The port and host can change
+    assert_eq!(https_origin("http://example.com:8080/abc", "https://example.com:8443/abc"),
+        Some(String::from("https://example.com:8443")));
+    assert_eq!(https_origin("http://example.com/abc", "https://www.example.com/abc"),
+        Some(String::from("https://www.example.com")));
+
+    // But not the path, the scheme or which way the redirect goes
+    assert_eq!(https_origin("http://example.com/abc", "https://example.com/login"), None);
+    assert_eq!(https_origin("http://example.com/abc", "http://www.example.com/abc"), None);
+    assert_eq!(https_origin("https://example.com/abc", "https://example.com/abc"), None);
+    assert_eq!(https_origin("https://example.com/abc", "http://example.com/abc"), None);
+}
+
+#[test]
+fn folders_redirecting_to_https_are_flagged() {
+    let responses = vec![
+        redirect("http://example.com:8080/a/xyz", "https://example.com/a/xyz"),
+        redirect("http://example.com:8080/a/xyzxyz", "https://example.com/a/xyzxyz"),
+        redirect("http://example.com:8080/a/xyzxyzxyz", "https://example.com/a/xyzxyzxyz")];
+    let validator = determine_not_found(responses).unwrap();
+    match &validator.validator_alert {
+        Some(ValidatorAlert::RedirectToHTTPS(origin)) => assert_eq!(origin, "https://example.com"),
+        _ => panic!("the folder should be flagged as redirecting to HTTPS")
+    }
+
+    // The folder is scanned on the HTTPS origin with --upgrade-https
+    assert_eq!(upgraded_url(&validator, "http://example.com:8080/a/"),
+        Some(String::from("https://example.com/a/")));
+}
+
+#[test]
+fn other_redirects_are_not_flagged() {
+    // Every path redirecting to a login page is a normal not found response
+    let responses = vec![
+        redirect("http://example.com/a/xyz", "https://example.com/login"),
+        redirect("http://example.com/a/xyzxyz", "https://example.com/login"),
+        redirect("http://example.com/a/xyzxyzxyz", "https://example.com/login")];
+    let validator = determine_not_found(responses).unwrap();
+    assert!(validator.validator_alert.is_none());
+    assert_eq!(validator.summary_text(), "(CODE:301|DEST:https://example.com/login)");
+    assert_eq!(upgraded_url(&validator, "http://example.com/a/"), None);
+
+    // Only a minority being upgraded isn't enough
+    let responses = vec![
+        redirect("http://example.com/a/xyz", "https://example.com/a/xyz"),
+        redirect("http://example.com/a/xyzxyz", "http://example.com/a/xyzxyz/"),
+        redirect("http://example.com/a/xyzxyzxyz", "http://example.com/a/xyzxyzxyz/")];
+    assert!(determine_not_found(responses).unwrap().validator_alert.is_none());
+
+    let validator = TargetValidator::new(404, None, None, None, None, None);
+    assert_eq!(upgraded_url(&validator, "http://example.com/a/"), None);
+}
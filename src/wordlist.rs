@@ -15,38 +15,71 @@
 // You should have received a copy of the GNU General Public License
 // along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{
-    process::exit,
-    sync::Arc,
-    fs::File,
-    io::prelude::*
-};
+use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+use serde::{Serialize, Deserialize};
 use percent_encoding::{utf8_percent_encode, DEFAULT_ENCODE_SET};
-use chardet::{detect, charset2encoding};
-use encoding::{
-    DecoderTrap,
-    label::encoding_from_whatwg_label
-};
+use crate::arg_parse::HttpVerb;
+use crate::mutation::{self, Rule};
+use crate::request::WORD_PLACEHOLDER;
 use crate::validator_thread::TargetValidator;
+use crate::wordlist_store::{self, Wordlist};
 
+#[cfg(test)]
+mod tests;
 
 // Struct for a UriGenerator, it needs the hostname, the suffix to append, a wordlist and an index into that wordlist
 pub struct UriGenerator {
     pub hostname: String,
-    prefix: String,
-    suffix: String,
+    pub verb: HttpVerb,
+    pub prefix: String,
+    pub suffix: String,
+    // Set for a request file which doesn't simply add the word to the folder
+    url_template: Option<String>,
     current_index: usize,
-    wordlist: Arc<Vec<String>>,
+    // The position in the wordlist of the word at the current index
+    offset: usize,
+    wordlist: Arc<Wordlist>,
+    // The words made from each word by these rules are requested after it
+    rules: Arc<Vec<Rule>>,
+    mutations: Vec<String>,
     step_size: usize,
     pub parent_depth: u32,
-    pub validator:Option<TargetValidator>
+    pub validator:Option<TargetValidator>,
+    // The url of the file whose backups are being requested, in which case
+    // the wordlist is the backup names made for it
+    pub derived_from: Option<String>,
+    // The index of the first word which may not have been fully
+    // scanned yet, shared with the main thread for checkpointing
+    progress: Arc<AtomicUsize>
+}
+
+// The position of a UriGenerator in its wordlist, saved to the state
+// file so that the scan can be resumed
+#[derive(Clone, Serialize, Deserialize)]
+pub struct JobState {
+    pub hostname: String,
+    #[serde(default)]
+    pub verb: HttpVerb,
+    pub prefix: String,
+    pub suffix: String,
+    pub current_index: usize,
+    pub step_size: usize,
+    pub parent_depth: u32,
+    pub validator: Option<TargetValidator>,
+    #[serde(default)]
+    pub derived_from: Option<String>,
+    // The backup names of a file are saved with the job, as they aren't
+    // in the wordlist
+    #[serde(default)]
+    pub words: Option<Vec<String>>
 }
 
 // Generates a new UriGenerator given various options
 impl UriGenerator {
-    pub fn new(mut hostname: String, prefix: String, suffix: String, 
-        wordlist: Arc<Vec<String>>, index: u32, step: u32, parent_depth:u32,
-        validator:Option<TargetValidator>) -> UriGenerator{
+    pub fn new(mut hostname: String, verb: HttpVerb, prefix: String, suffix: String, 
+        url_template: Option<String>, wordlist: Arc<Wordlist>, rules: Arc<Vec<Rule>>,
+        index: u32, step: u32, parent_depth:u32, validator:Option<TargetValidator>)
+        -> UriGenerator{
         // Remove a trailing / characters from the url if there is one
         if hostname.ends_with("/") {
             hostname.pop();
@@ -54,68 +87,147 @@ impl UriGenerator {
         
         UriGenerator { 
             hostname,
+            verb,
             prefix,
             suffix,
+            url_template,
             current_index: index as usize,
+            offset: wordlist.offset_of(index as usize),
             wordlist,
+            rules,
+            mutations: Vec::new(),
             step_size: step as usize,
             parent_depth,
-            validator
+            validator,
+            derived_from: None,
+            progress: Arc::new(AtomicUsize::new(index as usize))
         }
     }
+
+    // Creates a UriGenerator requesting the backups of a file found in the
+    // folder, which are flagged as derived from it
+    pub fn for_backups(hostname: String, verb: HttpVerb, derived_from: String,
+        backups: Vec<String>, parent_depth: u32, validator: Option<TargetValidator>)
+        -> UriGenerator {
+        let mut uri_gen = UriGenerator::new(hostname, verb, String::new(), String::new(),
+            None, Arc::new(Wordlist::from_words(&backups)), Arc::new(Vec::new()), 0, 1,
+            parent_depth, validator);
+        uri_gen.derived_from = Some(derived_from);
+        uri_gen
+    }
+
+    // Recreates a UriGenerator from a saved state
+    pub fn from_state(job: JobState, url_template: Option<String>,
+                      wordlist: Arc<Wordlist>, rules: Arc<Vec<Rule>>) -> UriGenerator {
+        // Backup names are requested as they are
+        let (wordlist, rules) = match job.words {
+            Some(words) => (Arc::new(Wordlist::from_words(&words)), Arc::new(Vec::new())),
+            None => (wordlist, rules)
+        };
+
+        UriGenerator {
+            hostname: job.hostname,
+            verb: job.verb,
+            prefix: job.prefix,
+            suffix: job.suffix,
+            url_template,
+            current_index: job.current_index,
+            offset: wordlist.offset_of(job.current_index),
+            wordlist,
+            rules,
+            mutations: Vec::new(),
+            step_size: job.step_size,
+            parent_depth: job.parent_depth,
+            validator: job.validator,
+            derived_from: job.derived_from,
+            progress: Arc::new(AtomicUsize::new(job.current_index))
+        }
+    }
+
+    pub fn job_state(&self) -> JobState {
+        JobState {
+            hostname: self.hostname.clone(),
+            verb: self.verb,
+            prefix: self.prefix.clone(),
+            suffix: self.suffix.clone(),
+            current_index: self.progress.load(Ordering::SeqCst),
+            step_size: self.step_size,
+            parent_depth: self.parent_depth,
+            validator: self.validator.clone(),
+            derived_from: self.derived_from.clone(),
+            words: self.derived_from.as_ref().map(|_| self.wordlist.iter().collect())
+        }
+    }
+
+    pub fn progress(&self) -> Arc<AtomicUsize> {
+        self.progress.clone()
+    }
+
+    // Records how far through the wordlist the scan has got, given the
+    // number of generated uris which haven't been responded to yet
+    // Each pending uri is counted as a separate word, so mutated words
+    // may be requested again when resuming but are never skipped
+    pub fn update_progress(&self, pending: usize) {
+        let unfinished = if self.mutations.is_empty() { pending } else { pending + 1 };
+        let index = self.current_index.saturating_sub(unfinished * self.step_size);
+        self.progress.store(index, Ordering::SeqCst);
+    }
 }
 
-// Defines iterating over a UriGenerator
+// Defines iterating over a UriGenerator, giving each uri along with
+// the word from the wordlist it was made from
 impl Iterator for UriGenerator {
-    type Item = (String);
+    type Item = (String, String);
 
     fn next(&mut self) -> Option<Self::Item> {
         
-        // If we're at the end of the wordlist then return None
-        if self.current_index >= self.wordlist.len() {
-            return None;
-        }
-        // Concatenate the hostname with the current wordlist item and the suffix, then url encode
-        let uri = self.hostname.clone() + "/" + &self.prefix + &self.wordlist[self.current_index].clone() + &self.suffix;
-        let uri = utf8_percent_encode(&uri, DEFAULT_ENCODE_SET).to_string();
+        // The mutations of the last word are requested before moving on
+        let word = match self.mutations.pop() {
+            Some(word) => word,
+            None => {
+                // If we're at the end of the wordlist then return None
+                if self.current_index >= self.wordlist.len() {
+                    return None;
+                }
+                let (word, next) = self.wordlist.word_at(self.offset);
+                self.mutations = mutation::mutations(&self.rules, &word);
+                self.mutations.reverse();
+
+                // Maintain the index and position in the wordlist
+                self.current_index += self.step_size;
+                self.offset = self.wordlist.skip(next, self.step_size - 1);
+                word
+            }
+        };
+
+        // Concatenate the hostname with the word and the suffix, then url encode
+        let uri = match &self.url_template {
+            Some(template) =>
+                url_from_template(template, &(self.prefix.clone() + &word + &self.suffix)),
+            None => {
+                let uri = self.hostname.clone() + "/" + &self.prefix + &word + &self.suffix;
+                utf8_percent_encode(&uri, DEFAULT_ENCODE_SET).to_string()
+            }
+        };
 
-        // Maintain the index into the wordlist
-        self.current_index += self.step_size;
         // Return the generated Uri
-        Some(uri)
+        Some((uri, word))
 
     }
 }
 
-// Function used to read in lines from the wordlist file
+// Puts a word, along with its prefix and extension, into the url from a
+// request file. Only the word is url encoded, the rest of the url is
+// used as it was given
+pub fn url_from_template(template: &str, word: &str) -> String {
+    template.replace(WORD_PLACEHOLDER, &utf8_percent_encode(word, DEFAULT_ENCODE_SET).to_string())
+}
+
+// Function used to read in the lines of a file, such as a list of hosts
+// or extensions
 pub fn lines_from_file(filename: String) -> Vec<String>
 {
-    let mut file = File::open(filename.clone())
-        .unwrap_or_else(|error| { println!("Opening file \"{}\" failed: {}", filename, error); exit(2);});
-    let mut reader: Vec<u8> = Vec::new();
-
-    // Read the raw file in as a vector of bytes
-    file.read_to_end(&mut reader)
-        .expect("Error reading file");
-
-    // Detect the charset of the file
-    let result = detect(&reader);
-    // result.0 Encode
-    // result.1 Confidence
-    // result.2 Language
-
-    // Decode the file into UTF-8 from the guessed encoding
-    let coder = encoding_from_whatwg_label(charset2encoding(&result.0));
-    match coder {
-        Some(coding) => {
-            return coding.decode(&reader, DecoderTrap::Ignore)
-                .expect("Error decoding to UTF-8")
-                .lines()
-                .map(|s| String::from(s))
-                .collect();
-        },
-        None => {
-            panic!("Error detecting file encoding");
-        }
-    }
+    let mut lines = Vec::new();
+    wordlist_store::read_lines(&filename, |line| lines.push(String::from(line)));
+    lines
 }
\ No newline at end of file
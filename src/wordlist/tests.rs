@@ -0,0 +1,112 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::{Arc, atomic::Ordering};
+use crate::arg_parse::HttpVerb;
+use crate::mutation::Rule;
+use crate::validator_thread::TargetValidator;
+use crate::wordlist_store::Wordlist;
+use super::UriGenerator;
+
+fn wordlist(count: usize) -> Arc<Wordlist> {
+    let words: Vec<String> = (0..count).map(|index| format!("word{}", index)).collect();
+    Arc::new(Wordlist::from_words(&words))
+}
+
+fn generator(wordlist: Arc<Wordlist>, rules: Vec<Rule>, index: u32, step: u32) -> UriGenerator {
+    UriGenerator::new(String::from("http://example.com/"), HttpVerb::Get, String::new(),
+        String::from(".php"), None, wordlist, Arc::new(rules), index, step, 0,
+        Some(TargetValidator::new(404, None, None, None, None, None)))
+}
+
+fn words(uri_gen: &mut UriGenerator, count: usize) -> Vec<String> {
+    uri_gen.take(count).map(|(_, word)| word).collect()
+}
+
+#[test]
+fn words_are_split_between_jobs() {
+    let mut uri_gen = generator(wordlist(7), Vec::new(), 1, 3);
+    let uris: Vec<String> = uri_gen.by_ref().map(|(uri, _)| uri).collect();
+    assert_eq!(uris, vec!["http://example.com/word1.php", "http://example.com/word4.php"]);
+}
+
+#[test]
+fn progress_leaves_out_pending_words() {
+    let mut uri_gen = generator(wordlist(20), Vec::new(), 2, 3);
+    assert_eq!(words(&mut uri_gen, 4), vec!["word2", "word5", "word8", "word11"]);
+
+    // Each pending word goes back one step in the wordlist
+    uri_gen.update_progress(0);
+    assert_eq!(uri_gen.progress().load(Ordering::SeqCst), 14);
+    uri_gen.update_progress(2);
+    assert_eq!(uri_gen.progress().load(Ordering::SeqCst), 8);
+    assert_eq!(uri_gen.job_state().current_index, 8);
+
+    // But never before the start of the wordlist
+    uri_gen.update_progress(10);
+    assert_eq!(uri_gen.progress().load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn progress_includes_word_with_pending_mutations() {
+    let rules = vec![Rule::parse("$1").unwrap(), Rule::parse("$2").unwrap()];
+    let mut uri_gen = generator(wordlist(5), rules, 0, 1);
+    assert_eq!(words(&mut uri_gen, 2), vec!["word0", "word01"]);
+
+    // word02 still has to be generated, so word0 hasn't been finished
+    // even though nothing is pending
+    uri_gen.update_progress(0);
+    assert_eq!(uri_gen.progress().load(Ordering::SeqCst), 0);
+
+    assert_eq!(words(&mut uri_gen, 2), vec!["word02", "word1"]);
+    uri_gen.update_progress(0);
+    assert_eq!(uri_gen.progress().load(Ordering::SeqCst), 1);
+    uri_gen.update_progress(1);
+    assert_eq!(uri_gen.progress().load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn jobs_are_resumed_from_their_state() {
+    let words = wordlist(20);
+    let mut uri_gen = generator(words.clone(), Vec::new(), 1, 2);
+    assert_eq!(self::words(&mut uri_gen, 3), vec!["word1", "word3", "word5"]);
+    uri_gen.update_progress(1);
+
+    let job = uri_gen.job_state();
+    assert_eq!(job.words, None);
+    let mut resumed = UriGenerator::from_state(job, None, words, Arc::new(Vec::new()));
+    assert_eq!(resumed.hostname, "http://example.com");
+    assert_eq!(resumed.suffix, ".php");
+    assert_eq!(self::words(&mut resumed, 2), vec!["word5", "word7"]);
+    assert!(resumed.validator.is_some());
+}
+
+#[test]
+fn backup_jobs_are_resumed_with_their_words() {
+    let backups = vec![String::from("config.php.bak"), String::from("config.old")];
+    let uri_gen = UriGenerator::for_backups(String::from("http://example.com"), HttpVerb::Get,
+        String::from("http://example.com/config.php"), backups.clone(), 0, None);
+
+    let job = uri_gen.job_state();
+    assert_eq!(job.words, Some(backups.clone()));
+
+    // The backup names are used instead of the wordlist, without rules
+    let rules = Arc::new(vec![Rule::parse("u").unwrap()]);
+    let mut resumed = UriGenerator::from_state(job, None, wordlist(20), rules);
+    assert_eq!(resumed.derived_from, Some(String::from("http://example.com/config.php")));
+    assert_eq!(words(&mut resumed, 5), backups);
+}
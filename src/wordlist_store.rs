@@ -0,0 +1,632 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, hash_map::DefaultHasher},
+    fs::File,
+    hash::{Hash, Hasher},
+    io::{self, prelude::*, BufReader, BufWriter, SeekFrom},
+    process::exit,
+};
+use chardet::{detect, charset2encoding};
+use encoding::{
+    label::encoding_from_whatwg_label,
+    types::RawDecoder
+};
+use flate2::read::MultiGzDecoder;
+use memmap::Mmap;
+use crate::arg_parse::WordlistDedup;
+
+#[cfg(test)]
+mod tests;
+
+// The name given instead of a file to read from stdin
+pub const STDIN_NAME: &str = "-";
+
+// The number of bytes at the start of a file used to detect its charset
+const SAMPLE_SIZE: u64 = 65536;
+
+// The most words held in memory at once when deduplicating on disk, the
+// rest are sorted in chunks of this size and merged from temporary files
+const CHUNK_BYTES: usize = 64 * 1024 * 1024;
+
+// Bloom filters start sized for the words expected from the size of the
+// files, and add a stage twice as large whenever the last one fills up.
+// Each stage has half the error rate of the one before, so the chance of
+// a word being wrongly taken as a duplicate stays under twice this however
+// many words there are
+const BLOOM_ERROR_RATE: f64 = 0.0001;
+const BLOOM_BYTES_PER_WORD: u64 = 8;
+// Compressed files and stdin don't give their size up front, so their
+// filters start at the minimum size
+const BLOOM_MIN_CAPACITY: usize = 1 << 16;
+// Stages at least this large are mentioned, so that the memory used isn't
+// a surprise
+const BLOOM_NOTICE_BYTES: usize = 64 * 1024 * 1024;
+
+// The words to scan, as UTF-8 lines either memory mapped from a file or
+// held in one buffer, so that each word isn't a separate allocation.
+// Words are found by walking through the lines, so are read by moving an
+// offset through the wordlist rather than by their index
+pub struct Wordlist {
+    data: WordlistData,
+    len: usize
+}
+
+enum WordlistData {
+    Mapped(Mmap),
+    Owned(Vec<u8>)
+}
+
+impl Wordlist {
+    // Reads the wordlists into a single wordlist, removing duplicates in
+    // the given way. Exits if any of the files can't be read
+    pub fn load(filenames: &[String], dedup: WordlistDedup) -> Wordlist {
+        match dedup {
+            WordlistDedup::Memory => {
+                let mut words = Vec::new();
+                for filename in filenames {
+                    read_lines(filename, |word| words.push(String::from(word)));
+                }
+                words.sort();
+                words.dedup();
+                Wordlist::from_words(&words)
+            }
+            WordlistDedup::Disk => {
+                let mut sorter = ExternalSorter::new();
+                for filename in filenames {
+                    read_lines(filename, |word| sorter.add(word));
+                }
+                Wordlist::from_file(sorter.finish())
+            }
+            WordlistDedup::Bloom => {
+                let mut filter = BloomFilter::for_files(filenames);
+                let mut spool = Spool::new();
+                for filename in filenames {
+                    read_lines(filename, |word| if filter.insert(word) { spool.add(word) });
+                }
+                Wordlist::from_file(spool.finish())
+            }
+            WordlistDedup::Off => {
+                // A single UTF-8 file can be mapped as it is
+                if filenames.len() == 1 {
+                    if let Some(file) = open_plain(&filenames[0]) {
+                        return Wordlist::from_file(file);
+                    }
+                }
+                let mut spool = Spool::new();
+                for filename in filenames {
+                    read_lines(filename, |word| spool.add(word));
+                }
+                Wordlist::from_file(spool.finish())
+            }
+        }
+    }
+
+    pub fn from_words(words: &[String]) -> Wordlist {
+        let mut data = Vec::with_capacity(words.iter().map(|word| word.len() + 1).sum());
+        for word in words {
+            data.extend_from_slice(word.as_bytes());
+            data.push(b'\n');
+        }
+
+        Wordlist {
+            data: WordlistData::Owned(data),
+            len: words.len()
+        }
+    }
+
+    fn from_file(file: File) -> Wordlist {
+        let length = file.metadata()
+            .unwrap_or_else(|error| { println!("Reading wordlist failed: {}", error); exit(2); })
+            .len();
+        // Empty files can't be mapped
+        if length == 0 {
+            return Wordlist::from_words(&[]);
+        }
+
+        // The files mapped are either temporary files only used here or
+        // wordlists, which shouldn't be changed while they are scanned
+        let map = unsafe { Mmap::map(&file) }
+            .unwrap_or_else(|error| { println!("Mapping wordlist failed: {}", error); exit(2); });
+
+        let mut len = memchr::memchr_iter(b'\n', &map).count();
+        if !map.ends_with(b"\n") {
+            len += 1;
+        }
+
+        Wordlist {
+            data: WordlistData::Mapped(map),
+            len
+        }
+    }
+
+    #[inline]
+    fn bytes(&self) -> &[u8] {
+        match &self.data {
+            WordlistData::Mapped(map) => map,
+            WordlistData::Owned(data) => data
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // The offset of the word with the given index
+    pub fn offset_of(&self, index: usize) -> usize {
+        self.skip(0, index)
+    }
+
+    // Moves the offset on past the given number of words
+    pub fn skip(&self, mut offset: usize, count: usize) -> usize {
+        let bytes = self.bytes();
+        for _ in 0..count {
+            offset = match memchr::memchr(b'\n', &bytes[offset.min(bytes.len())..]) {
+                Some(index) => offset + index + 1,
+                None => return bytes.len()
+            };
+        }
+        offset
+    }
+
+    // The word at the offset, which must be the start of a word, along
+    // with the offset of the word after it
+    pub fn word_at(&self, offset: usize) -> (String, usize) {
+        let bytes = self.bytes();
+        let (end, next) = match memchr::memchr(b'\n', &bytes[offset..]) {
+            Some(index) => (offset + index, offset + index + 1),
+            None => (bytes.len(), bytes.len())
+        };
+        let line = &bytes[offset..end];
+        let line = if line.ends_with(b"\r") { &line[0..line.len()-1] } else { line };
+
+        (String::from_utf8_lossy(line).into_owned(), next)
+    }
+
+    pub fn iter(&self) -> Words<'_> {
+        Words {
+            wordlist: self,
+            index: 0,
+            offset: 0
+        }
+    }
+}
+
+pub struct Words<'a> {
+    wordlist: &'a Wordlist,
+    index: usize,
+    offset: usize
+}
+
+impl<'a> Iterator for Words<'a> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        if self.index >= self.wordlist.len() {
+            return None;
+        }
+        let (word, next) = self.wordlist.word_at(self.offset);
+        self.index += 1;
+        self.offset = next;
+        Some(word)
+    }
+}
+
+// Calls the function with each line of a file, decoded to UTF-8 and
+// without its line ending. The file is read from stdin if its name is -,
+// and is decompressed if it is gzipped. Exits if the file can't be read
+pub fn read_lines<F: FnMut(&str)>(filename: &str, mut function: F) {
+    let mut reader = open_decoded(filename);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {},
+            Err(error) => {
+                println!("Reading file \"{}\" failed: {}", filename, error);
+                exit(2);
+            }
+        }
+
+        if line.ends_with(b"\n") {
+            line.pop();
+        }
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+        function(&String::from_utf8_lossy(&line));
+    }
+}
+
+// Opens a file, or stdin, decompressing it if it starts with the gzip
+// magic number
+fn open_stream(filename: &str) -> Box<dyn BufRead> {
+    let reader: Box<dyn Read> = if filename == STDIN_NAME {
+        Box::new(io::stdin())
+    }
+    else {
+        Box::new(File::open(filename)
+            .unwrap_or_else(|error| {
+                println!("Opening file \"{}\" failed: {}", filename, error);
+                exit(2);
+            }))
+    };
+
+    let mut reader = BufReader::new(reader);
+    let is_gzip = reader.fill_buf()
+        .map(|start| start.starts_with(&[0x1f, 0x8b]))
+        .unwrap_or(false);
+    if is_gzip {
+        Box::new(BufReader::new(MultiGzDecoder::new(reader)))
+    }
+    else {
+        Box::new(reader)
+    }
+}
+
+// Opens a file as a stream of UTF-8, decoding it from the charset
+// detected from its start
+fn open_decoded(filename: &str) -> Box<dyn BufRead> {
+    let mut reader = open_stream(filename);
+    let mut sample = Vec::new();
+    if let Err(error) = reader.by_ref().take(SAMPLE_SIZE).read_to_end(&mut sample) {
+        println!("Reading file \"{}\" failed: {}", filename, error);
+        exit(2);
+    }
+
+    let decoder = if is_utf8(&sample) { None }
+    else {
+        encoding_from_whatwg_label(charset2encoding(&detect(&sample).0))
+            .map(|encoding| encoding.raw_decoder())
+    };
+    let reader = io::Cursor::new(sample).chain(reader);
+
+    match decoder {
+        Some(decoder) => Box::new(BufReader::new(DecodingReader::new(reader, decoder))),
+        // Files whose charset can't be detected are read as UTF-8, with
+        // any invalid characters replaced
+        None => Box::new(BufReader::new(reader))
+    }
+}
+
+// Opens a file to be mapped as it is, if it is a file rather than stdin
+// and isn't compressed or in a charset other than UTF-8
+fn open_plain(filename: &str) -> Option<File> {
+    if filename == STDIN_NAME {
+        return None;
+    }
+
+    let mut file = File::open(filename)
+        .unwrap_or_else(|error| {
+            println!("Opening file \"{}\" failed: {}", filename, error);
+            exit(2);
+        });
+    let mut sample = Vec::new();
+    let read = Read::by_ref(&mut file).take(SAMPLE_SIZE).read_to_end(&mut sample)
+        .and_then(|_| file.seek(SeekFrom::Start(0)));
+    if let Err(error) = read {
+        println!("Reading file \"{}\" failed: {}", filename, error);
+        exit(2);
+    }
+
+    if sample.starts_with(&[0x1f, 0x8b]) || !is_utf8(&sample) {
+        None
+    }
+    else {
+        Some(file)
+    }
+}
+
+#[inline]
+fn is_utf8(sample: &[u8]) -> bool {
+    sample.is_ascii() || detect(sample).0.eq_ignore_ascii_case("utf-8")
+}
+
+// Decodes a stream in another charset to UTF-8 as it is read
+struct DecodingReader<R> {
+    inner: R,
+    decoder: Box<dyn RawDecoder>,
+    output: Vec<u8>,
+    position: usize,
+    finished: bool
+}
+
+impl<R: Read> DecodingReader<R> {
+    fn new(inner: R, decoder: Box<dyn RawDecoder>) -> DecodingReader<R> {
+        DecodingReader {
+            inner,
+            decoder,
+            output: Vec::new(),
+            position: 0,
+            finished: false
+        }
+    }
+
+    // Decodes the next block of input, skipping invalid sequences
+    fn fill(&mut self) -> io::Result<()> {
+        let mut input = [0; 8192];
+        let read = self.inner.read(&mut input)?;
+        let mut output = String::new();
+
+        if read == 0 {
+            self.decoder.raw_finish(&mut output);
+            self.finished = true;
+        }
+        let mut remaining = 0;
+        while remaining < read {
+            match self.decoder.raw_feed(&input[remaining..read], &mut output) {
+                (_, Some(error)) =>
+                    remaining = (remaining as isize + error.upto).max(remaining as isize + 1) as usize,
+                (_, None) => remaining = read
+            }
+        }
+
+        self.output = output.into_bytes();
+        self.position = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.output.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+
+        let count = buf.len().min(self.output.len() - self.position);
+        buf[0..count].copy_from_slice(&self.output[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+// A temporary file the words are written to, one per line
+struct Spool {
+    writer: BufWriter<File>
+}
+
+impl Spool {
+    fn new() -> Spool {
+        Spool {
+            writer: BufWriter::new(temporary_file())
+        }
+    }
+
+    fn add(&mut self, word: &str) {
+        let written = self.writer.write_all(word.as_bytes())
+            .and_then(|_| self.writer.write_all(b"\n"));
+        if let Err(error) = written {
+            println!("Writing temporary wordlist failed: {}", error);
+            exit(2);
+        }
+    }
+
+    // Returns the file, ready to be read from the start
+    fn finish(self) -> File {
+        let file = self.writer.into_inner().map_err(io::Error::from)
+            .and_then(|mut file| file.seek(SeekFrom::Start(0)).map(|_| file));
+        file.unwrap_or_else(|error| {
+            println!("Writing temporary wordlist failed: {}", error);
+            exit(2);
+        })
+    }
+}
+
+fn temporary_file() -> File {
+    tempfile::tempfile()
+        .unwrap_or_else(|error| {
+            println!("Creating temporary wordlist failed: {}", error);
+            exit(2);
+        })
+}
+
+// Sorts and deduplicates words which may not fit in memory, by sorting
+// chunks of them into temporary files then merging the files
+struct ExternalSorter {
+    chunk: Vec<String>,
+    chunk_bytes: usize,
+    chunks: Vec<File>
+}
+
+impl ExternalSorter {
+    fn new() -> ExternalSorter {
+        ExternalSorter {
+            chunk: Vec::new(),
+            chunk_bytes: 0,
+            chunks: Vec::new()
+        }
+    }
+
+    fn add(&mut self, word: &str) {
+        self.chunk_bytes += word.len();
+        self.chunk.push(String::from(word));
+        if self.chunk_bytes >= CHUNK_BYTES {
+            self.write_chunk();
+        }
+    }
+
+    fn write_chunk(&mut self) {
+        self.chunk.sort();
+        self.chunk.dedup();
+        let mut spool = Spool::new();
+        for word in self.chunk.drain(..) {
+            spool.add(&word);
+        }
+        self.chunks.push(spool.finish());
+        self.chunk_bytes = 0;
+    }
+
+    // Merges the sorted chunks into a file of the unique words in order
+    fn finish(mut self) -> File {
+        if !self.chunk.is_empty() || self.chunks.is_empty() {
+            self.write_chunk();
+        }
+
+        let mut readers: Vec<io::Lines<BufReader<File>>> = self.chunks.into_iter()
+            .map(|chunk| BufReader::new(chunk).lines())
+            .collect();
+        let mut heap = BinaryHeap::new();
+        for (index, reader) in readers.iter_mut().enumerate() {
+            if let Some(word) = next_word(reader) {
+                heap.push(Reverse((word, index)));
+            }
+        }
+
+        let mut spool = Spool::new();
+        let mut last: Option<String> = None;
+        while let Some(Reverse((word, index))) = heap.pop() {
+            if let Some(next) = next_word(&mut readers[index]) {
+                heap.push(Reverse((next, index)));
+            }
+            if last.as_ref() != Some(&word) {
+                spool.add(&word);
+                last = Some(word);
+            }
+        }
+        spool.finish()
+    }
+}
+
+#[inline]
+fn next_word(reader: &mut io::Lines<BufReader<File>>) -> Option<String> {
+    reader.next().map(|word| word.unwrap_or_else(|error| {
+        println!("Reading temporary wordlist failed: {}", error);
+        exit(2);
+    }))
+}
+
+// A bloom filter of the words seen so far, which uses far less memory
+// than keeping the words but may rarely report a new word as seen
+struct BloomFilter {
+    stages: Vec<BloomStage>
+}
+
+struct BloomStage {
+    bits: Vec<u64>,
+    size: u64,
+    hashes: u64,
+    error_rate: f64,
+    // The number of words the stage holds before the next is added
+    capacity: usize,
+    count: usize
+}
+
+impl BloomFilter {
+    // Creates a filter sized for the total size of the files which
+    // aren't compressed
+    fn for_files(filenames: &[String]) -> BloomFilter {
+        let input_size: u64 = filenames.iter()
+            .filter(|filename| *filename != STDIN_NAME)
+            .filter_map(|filename| {
+                let mut file = File::open(filename).ok()?;
+                let mut magic = [0; 2];
+                let is_gzip = file.read_exact(&mut magic).is_ok() && magic == [0x1f, 0x8b];
+                if is_gzip { None } else { file.metadata().ok().map(|metadata| metadata.len()) }
+            })
+            .sum();
+        BloomFilter::new((input_size / BLOOM_BYTES_PER_WORD) as usize)
+    }
+
+    fn new(capacity: usize) -> BloomFilter {
+        BloomFilter {
+            stages: vec![BloomStage::new(capacity.max(BLOOM_MIN_CAPACITY), BLOOM_ERROR_RATE)]
+        }
+    }
+
+    // Adds the word to the filter, returning false if it may already
+    // have been added
+    fn insert(&mut self, word: &str) -> bool {
+        let mut hasher = DefaultHasher::new();
+        word.hash(&mut hasher);
+        let first = hasher.finish();
+        // The second hash is made odd so that it is never zero
+        1u8.hash(&mut hasher);
+        let second = hasher.finish() | 1;
+
+        if self.stages.iter().any(|stage| stage.contains(first, second)) {
+            return false;
+        }
+
+        let (capacity, error_rate, full) = {
+            let last = self.stages.last().unwrap();
+            (last.capacity, last.error_rate, last.count >= last.capacity)
+        };
+        if full {
+            let stage = BloomStage::new(capacity * 2, error_rate / 2.0);
+            let notice = stage.bits.len() * 8 >= BLOOM_NOTICE_BYTES;
+            self.stages.push(stage);
+            if notice {
+                println!("Removing duplicate words now uses {}MiB of memory, use \
+                    --wordlist-dedup disk to use less", self.memory() / (1024 * 1024));
+            }
+        }
+
+        self.stages.last_mut().unwrap().insert(first, second);
+        true
+    }
+
+    // The number of bytes used by the filter
+    fn memory(&self) -> usize {
+        self.stages.iter().map(|stage| stage.bits.len() * 8).sum()
+    }
+}
+
+impl BloomStage {
+    // Sizes the stage so that once it is full, a word which isn't in it
+    // is wrongly reported as in it at the given rate
+    fn new(capacity: usize, error_rate: f64) -> BloomStage {
+        let ln_2 = std::f64::consts::LN_2;
+        let size = (capacity as f64 * -error_rate.ln() / (ln_2 * ln_2)).ceil() as u64;
+        let size = (size + 63) / 64 * 64;
+        BloomStage {
+            bits: vec![0; (size / 64) as usize],
+            size,
+            hashes: (-error_rate.log2()).ceil() as u64,
+            error_rate,
+            capacity,
+            count: 0
+        }
+    }
+
+    // The bits for a word are found by double hashing with the two hashes
+    #[inline]
+    fn bit(&self, first: u64, second: u64, i: u64) -> (usize, u64) {
+        let bit = first.wrapping_add(i.wrapping_mul(second)) % self.size;
+        ((bit / 64) as usize, 1u64 << (bit % 64))
+    }
+
+    fn contains(&self, first: u64, second: u64) -> bool {
+        (0..self.hashes).all(|i| {
+            let (index, mask) = self.bit(first, second, i);
+            self.bits[index] & mask != 0
+        })
+    }
+
+    fn insert(&mut self, first: u64, second: u64) {
+        for i in 0..self.hashes {
+            let (index, mask) = self.bit(first, second, i);
+            self.bits[index] |= mask;
+        }
+        self.count += 1;
+    }
+}
@@ -0,0 +1,140 @@
+// This file is part of Dirble - https://www.github.com/nccgroup/dirble
+// Copyright (C) 2019 Izzy Whistlecroft <Izzy(dot)Whistlecroft(at)nccgroup(dot)com>
+// Released as open source by NCC Group Plc - https://www.nccgroup.com/
+//
+// Dirble is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Dirble is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Dirble.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io::{Read, Write};
+use encoding::{all::UTF_16LE, types::Encoding};
+use flate2::{write::GzEncoder, Compression};
+use tempfile::NamedTempFile;
+use crate::arg_parse::WordlistDedup;
+use super::{read_lines, BloomFilter, DecodingReader, Wordlist};
+
+fn words(words: &[&str]) -> Vec<String> {
+    words.iter().map(|word| String::from(*word)).collect()
+}
+
+fn write_file(contents: &[u8]) -> NamedTempFile {
+    let mut file = NamedTempFile::new().unwrap();
+    file.write_all(contents).unwrap();
+    file.flush().unwrap();
+    file
+}
+
+fn path(file: &NamedTempFile) -> String {
+    file.path().to_str().unwrap().to_string()
+}
+
+fn load(files: &[&NamedTempFile], dedup: WordlistDedup) -> Vec<String> {
+    let filenames: Vec<String> = files.iter().map(|file| path(file)).collect();
+    Wordlist::load(&filenames, dedup).iter().collect()
+}
+
+#[test]
+fn words_are_read_by_offset() {
+    let wordlist = Wordlist::from_words(&words(&["admin", "", "login"]));
+    assert_eq!(wordlist.len(), 3);
+
+    let (word, next) = wordlist.word_at(0);
+    assert_eq!(word, "admin");
+    let (word, next) = wordlist.word_at(next);
+    assert_eq!(word, "");
+    assert_eq!(wordlist.word_at(next).0, "login");
+
+    assert_eq!(wordlist.offset_of(2), next);
+    assert_eq!(wordlist.word_at(wordlist.skip(0, 2)).0, "login");
+    // Skipping past the end stops at the end
+    assert_eq!(wordlist.skip(0, 10), wordlist.offset_of(3));
+}
+
+#[test]
+fn mapped_files_are_split_into_lines() {
+    // Windows line endings are removed and the last line doesn't need to
+    // end in a newline
+    let file = write_file(b"admin\r\nlogin\nadmin\r\nbackup");
+    assert_eq!(load(&[&file], WordlistDedup::Off),
+        words(&["admin", "login", "admin", "backup"]));
+}
+
+#[test]
+fn duplicates_are_removed() {
+    let first = write_file(b"login\nadmin\nlogin\n");
+    let second = write_file(b"backup\nadmin\n");
+    let expected = words(&["admin", "backup", "login"]);
+
+    assert_eq!(load(&[&first, &second], WordlistDedup::Memory), expected);
+    assert_eq!(load(&[&first, &second], WordlistDedup::Disk), expected);
+    // Bloom filters keep the order the words were first seen in
+    assert_eq!(load(&[&first, &second], WordlistDedup::Bloom),
+        words(&["login", "admin", "backup"]));
+    assert_eq!(load(&[&first, &second], WordlistDedup::Off),
+        words(&["login", "admin", "login", "backup", "admin"]));
+}
+
+#[test]
+fn empty_files_are_loaded() {
+    let file = write_file(b"");
+    assert!(load(&[&file], WordlistDedup::Off).is_empty());
+    assert!(load(&[&file], WordlistDedup::Disk).is_empty());
+}
+
+#[test]
+fn gzipped_files_are_decompressed() {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(b"admin\nlogin\n").unwrap();
+    let file = write_file(&encoder.finish().unwrap());
+
+    let mut lines = Vec::new();
+    read_lines(&path(&file), |line| lines.push(String::from(line)));
+    assert_eq!(lines, words(&["admin", "login"]));
+
+    // Compressed files can't be mapped as they are
+    assert_eq!(load(&[&file], WordlistDedup::Off), words(&["admin", "login"]));
+}
+
+#[test]
+fn other_charsets_are_decoded() {
+    let input = UTF_16LE.encode("admin\nlogin\n", encoding::EncoderTrap::Strict).unwrap();
+    let mut output = String::new();
+    DecodingReader::new(&input[..], UTF_16LE.raw_decoder())
+        .read_to_string(&mut output).unwrap();
+    assert_eq!(output, "admin\nlogin\n");
+}
+
+#[test]
+fn bloom_filter_finds_duplicates() {
+    let mut filter = BloomFilter::new(0);
+    assert!(filter.insert("admin"));
+    assert!(filter.insert("login"));
+    assert!(!filter.insert("admin"));
+    assert!(!filter.insert("login"));
+}
+
+#[test]
+fn bloom_filter_grows() {
+    // Far more words than the filter was sized for, as from stdin
+    let mut filter = BloomFilter::new(0);
+    let count = 500_000;
+    let wrongly_removed = (0..count)
+        .filter(|index| !filter.insert(&format!("word{}", index)))
+        .count();
+    assert!(filter.stages.len() > 1);
+    assert!(wrongly_removed * 5000 < count, "{} new words were taken as duplicates",
+        wrongly_removed);
+
+    // Words added to every stage are still found
+    assert!(!filter.insert("word0"));
+    assert!(!filter.insert(&format!("word{}", count - 1)));
+}